@@ -0,0 +1,86 @@
+//! Render time of a lit, textured and normal-mapped scene with FSAA, which shades every sample,
+//! against MSAA, which shades each pixel once.
+//!
+//! Run with `cargo bench --bench msaa`.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 3;
+/// Quads along each side of the image, two triangles each
+const GRID: usize = 16;
+
+/// A grid of triangles covering a 256x256 image, drawn with `mode` at `level`.
+fn script(mode: &str, level: u32) -> String {
+    let mut positions = String::new();
+    let mut texcoords = String::new();
+    let mut normals = String::new();
+    let mut tangents = String::new();
+    let step = 2f64 / GRID as f64;
+    for j in 0..GRID {
+        for i in 0..GRID {
+            let (x, y) = (-1f64 + i as f64 * step, -1f64 + j as f64 * step);
+            let corners = [[0, 0], [1, 0], [0, 1], [1, 0], [1, 1], [0, 1]];
+            for [d_x, d_y] in corners {
+                let (p_x, p_y) = (x + d_x as f64 * step, y + d_y as f64 * step);
+                write!(positions, " {} {}", p_x, p_y).unwrap();
+                write!(texcoords, " {} {}", (p_x + 1f64) * 2f64, (p_y + 1f64) * 2f64).unwrap();
+                normals.push_str(" 0 0 1");
+                tangents.push_str(" 1 0 0 1");
+            }
+        }
+    }
+
+    let count = GRID * GRID * 6;
+    return format!(
+        "png 256 256 out.png
+{mode} {level}
+texture block-I-white-background.png
+normalmap block-I-blue-background.png
+light 0.3 0.5 1 0.2
+position 2{positions}
+color 3{colors}
+texcoord 2{texcoords}
+normal 3{normals}
+tangent 4{tangents}
+drawArraysTriangles 0 {count}
+",
+        colors = " 1 1 1".repeat(count),
+    );
+}
+
+/// The best of a few renders, to keep scheduling noise out of the comparison.
+fn time(script: &str) -> Duration {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("input");
+    let output = std::env::temp_dir().join(format!("rasterizer-bench-msaa-{}.png", std::process::id()));
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rasterizer"))
+            .args(["render", "-o"])
+            .arg(&output)
+            .arg("-")
+            .current_dir(&input)
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+        assert!(child.wait().unwrap().success());
+        best = best.min(start.elapsed());
+    }
+    std::fs::remove_file(output).unwrap();
+    return best;
+}
+
+fn main() {
+    for level in [4, 8] {
+        let fsaa = time(&script("fsaa", level));
+        let msaa = time(&script("msaa", level));
+        println!("fsaa {}   {:>10.2?}", level, fsaa);
+        println!("msaa {}   {:>10.2?}", level, msaa);
+        println!("speedup  {:>10.1}x", fsaa.as_secs_f64() / msaa.as_secs_f64());
+    }
+}
@@ -0,0 +1,75 @@
+//! Vertex throughput of the fixed-size `Mat4` transform against the `ndarray` one it replaced.
+//!
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ndarray::{arr2, Array2};
+
+#[allow(dead_code)]
+#[path = "../src/math.rs"]
+mod math;
+
+use math::{Mat4, Vec4};
+
+const VERTICES: usize = 1_000_000;
+const ROUNDS: usize = 5;
+
+// The matrix from rast-2d3d.txt
+const MATRIX: [f64; 16] = [
+    -2.0801, -0.8870, 0.7107, 0.4264, 0.0, 1.9218, 1.0660, 0.6396, 1.3868, -1.3305, 1.0660, 0.6396, 0.0, 0.0, 2.4840,
+    4.6904,
+];
+
+fn vertices() -> Vec<[f64; 4]> {
+    return (0..VERTICES)
+        .map(|i| {
+            let t = i as f64 / VERTICES as f64;
+            [t, 1f64 - t, t * t, 1f64]
+        })
+        .collect();
+}
+
+/// The best of a few rounds, to keep scheduling noise out of the comparison.
+fn time<F: FnMut(&mut [f64; 4])>(vertices: &[[f64; 4]], mut transform: F) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let mut data = vertices.to_vec();
+        let start = Instant::now();
+        for vertex in data.iter_mut() {
+            transform(black_box(vertex));
+        }
+        black_box(&data);
+        best = best.min(start.elapsed());
+    }
+    return best;
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let throughput = VERTICES as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{:<8} {:>10.2?} {:>10.1} Mvertices/s", name, elapsed, throughput);
+}
+
+fn main() {
+    let vertices = vertices();
+
+    let mut array: Array2<f64> = Array2::zeros((4, 4));
+    for (j, &value) in MATRIX.iter().enumerate() {
+        array[[j % 4, j / 4]] = value;
+    }
+    let ndarray = time(&vertices, |vertex| {
+        let temp: Array2<f64> = arr2(&[[vertex[0]], [vertex[1]], [vertex[2]], [vertex[3]]]);
+        let result = array.dot(&temp);
+        *vertex = [result[[0, 0]], result[[1, 0]], result[[2, 0]], result[[3, 0]]];
+    });
+
+    let matrix = Mat4::from_column_major(MATRIX);
+    let mat4 = time(&vertices, |vertex| {
+        *vertex = (matrix * Vec4(*vertex)).0;
+    });
+
+    report("ndarray", ndarray);
+    report("Mat4", mat4);
+    println!("speedup  {:>10.1}x", ndarray.as_secs_f64() / mat4.as_secs_f64());
+}
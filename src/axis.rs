@@ -9,5 +9,16 @@ pub(crate) mod axis {
     pub(crate) const A: usize = 7;
     pub(crate) const S: usize = 8;
     pub(crate) const T: usize = 9;
-    pub(crate) const P: usize = 10;
-}
\ No newline at end of file
+    pub(crate) const NX: usize = 10;
+    pub(crate) const NY: usize = 11;
+    pub(crate) const NZ: usize = 12;
+    pub(crate) const TX: usize = 13;
+    pub(crate) const TY: usize = 14;
+    pub(crate) const TZ: usize = 15;
+    pub(crate) const TW: usize = 16;
+    pub(crate) const LX: usize = 17;
+    pub(crate) const LY: usize = 18;
+    pub(crate) const LZ: usize = 19;
+    pub(crate) const LW: usize = 20;
+    pub(crate) const P: usize = 21;
+}
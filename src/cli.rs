@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::process::ExitCode;
+
+use crate::script::Location;
+
+pub(crate) const USAGE: &str = "\
+Usage:
+  rasterizer render [options] <script>   Run a script, or read it from stdin with -
+  rasterizer <script>                    Same as render
+  rasterizer compare <a> <b> [heatmap]   Compare two images, writing a heatmap (default heatmap.png)
+  rasterizer help                        Show this message
+
+Render options:
+  -o, --output <file>        Write the image here instead of where the script says, in the
+                             format its extension names
+      --asset-dir <dir>      Where the script's textures are, rather than next to the script
+      --output-dir <dir>     Where the script's images go, rather than next to the script
+      --width <pixels>       Override the width of the script's output image
+      --height <pixels>      Override the height of the script's output image
+      --fsaa <level>         Antialias with this many samples per pixel along each axis, 1 to 8,
+                             overriding the script
+      --srgb                 Write sRGB-encoded output, overriding the script
+  -D, --define <key=value>   Set a variable, used in the script as $key
+  -q, --quiet                Don't report errors, only exit with their code
+  -v, --verbose              Also report each image written and the time taken
+
+Exit codes:
+  0  Success
+  1  The script, or the images to compare, had errors
+  2  Invalid arguments
+  3  A file couldn't be read or written
+";
+
+pub(crate) const SCRIPT_ERROR: u8 = 1;
+pub(crate) const USAGE_ERROR: u8 = 2;
+pub(crate) const IO_ERROR: u8 = 3;
+
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub(crate) enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+pub(crate) struct RenderArgs {
+    /// A path, or `-` for stdin
+    pub(crate) script: String,
+    pub(crate) output: Option<String>,
+    /// What paths in the script are relative to, instead of the file they're in
+    pub(crate) asset_dir: Option<String>,
+    pub(crate) output_dir: Option<String>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) fsaa: Option<u32>,
+    pub(crate) srgb: bool,
+    pub(crate) defines: HashMap<String, String>,
+    pub(crate) verbosity: Verbosity,
+}
+
+pub(crate) enum Command {
+    Render(RenderArgs),
+    Compare {
+        a: String,
+        b: String,
+        heatmap: Option<String>,
+    },
+    Help,
+}
+
+/// Parses the arguments after the program name. A first argument that isn't a subcommand is taken
+/// as a script to render, as before there were subcommands.
+pub(crate) fn parse(args: &[String]) -> Result<Command, &'static str> {
+    return match args.first().map(|arg| arg.as_str()) {
+        None => Err("Missing a command"),
+        Some("help" | "-h" | "--help") => Ok(Command::Help),
+        Some("render") => parse_render(&args[1..]),
+        Some("compare") => match &args[1..] {
+            [a, b] => Ok(Command::Compare {
+                a: a.clone(),
+                b: b.clone(),
+                heatmap: None,
+            }),
+            [a, b, heatmap] => Ok(Command::Compare {
+                a: a.clone(),
+                b: b.clone(),
+                heatmap: Some(heatmap.clone()),
+            }),
+            _ => Err("compare takes two images and an optional heatmap"),
+        },
+        Some(_) => parse_render(args),
+    };
+}
+
+fn parse_render(args: &[String]) -> Result<Command, &'static str> {
+    let mut render = RenderArgs {
+        script: String::default(),
+        output: None,
+        asset_dir: None,
+        output_dir: None,
+        width: None,
+        height: None,
+        fsaa: None,
+        srgb: false,
+        defines: HashMap::new(),
+        verbosity: Verbosity::Normal,
+    };
+    let mut script = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        // Options take their value from the next argument, or after an = in the same one
+        let (name, inline) = match args[i].split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(String::from(value))),
+            _ => (args[i].as_str(), None),
+        };
+        let mut value = || -> Result<String, &'static str> {
+            if let Some(value) = inline.clone() {
+                return Ok(value);
+            }
+            i += 1;
+            return args.get(i).cloned().ok_or("Missing a value for an option");
+        };
+
+        match name {
+            "-o" | "--output" => render.output = Some(value()?),
+            "--asset-dir" => render.asset_dir = Some(value()?),
+            "--output-dir" => render.output_dir = Some(value()?),
+            "--width" => render.width = Some(value()?.parse().map_err(|_| "Width must be an integer")?),
+            "--height" => render.height = Some(value()?.parse().map_err(|_| "Height must be an integer")?),
+            "--fsaa" => {
+                let fsaa = value()?.parse::<u32>().map_err(|_| "FSAA level must be an integer")?;
+                if !(1..=8).contains(&fsaa) {
+                    return Err("FSAA level must be within the range [1, 8]");
+                }
+                render.fsaa = Some(fsaa);
+            }
+            "--srgb" => render.srgb = true,
+            "-D" | "--define" => {
+                let define = value()?;
+                let (key, value) = define.split_once('=').ok_or("Defines must be of the form key=value")?;
+                render.defines.insert(String::from(key), String::from(value));
+            }
+            "-q" | "--quiet" => render.verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => render.verbosity = Verbosity::Verbose,
+            "-h" | "--help" => return Ok(Command::Help),
+            // A lone - is stdin rather than an option
+            _ if name.starts_with('-') && name != "-" => return Err("Unknown option"),
+            _ if script.is_some() => return Err("Only one script can be rendered at a time"),
+            _ => script = Some(args[i].clone()),
+        }
+        i += 1;
+    }
+
+    render.script = script.ok_or("Missing a script")?;
+    return Ok(Command::Render(render));
+}
+
+/// Reports what happens during a render according to its verbosity, and remembers the worst kind
+/// of failure for the exit code.
+pub(crate) struct Log {
+    verbosity: Verbosity,
+    script_errors: bool,
+    io_errors: bool,
+}
+
+impl Log {
+    pub(crate) fn new(verbosity: Verbosity) -> Self {
+        Self {
+            verbosity,
+            script_errors: false,
+            io_errors: false,
+        }
+    }
+
+    pub(crate) fn line_error(&mut self, location: &Location, err: &str) {
+        self.script_errors = true;
+        if self.verbosity > Verbosity::Quiet {
+            eprintln!("Error on {}: {}.", location, err);
+        }
+    }
+
+    /// A file that couldn't be read or written.
+    pub(crate) fn io_error(&mut self, err: impl Display) {
+        self.io_errors = true;
+        if self.verbosity > Verbosity::Quiet {
+            eprintln!("{}", err);
+        }
+    }
+
+    pub(crate) fn info(&self, message: impl Display) {
+        if self.verbosity == Verbosity::Verbose {
+            eprintln!("{}", message);
+        }
+    }
+
+    pub(crate) fn exit_code(&self) -> ExitCode {
+        if self.io_errors {
+            return ExitCode::from(IO_ERROR);
+        }
+        if self.script_errors {
+            return ExitCode::from(SCRIPT_ERROR);
+        }
+        return ExitCode::SUCCESS;
+    }
+}
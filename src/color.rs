@@ -1,3 +1,4 @@
+use crate::color_space::{convert, ColorSpace};
 use crate::point::Point;
 
 #[derive(Copy, Clone)]
@@ -18,6 +19,19 @@ impl Color {
             a,
         }
     }
+
+    pub(crate) fn convert(self, from: ColorSpace, to: ColorSpace) -> Self {
+        if from == to {
+            return self;
+        }
+        let [r, g, b] = convert([self.r as f32, self.g as f32, self.b as f32], from, to);
+        Self {
+            r: r as f64,
+            g: g as f64,
+            b: b as f64,
+            a: self.a,
+        }
+    }
 }
 
 impl From<Color> for Point<4> {
@@ -0,0 +1,208 @@
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult};
+use png::{BitDepth, ColorType, ScaledFloat, SourceChromaticities, SrgbRenderingIntent};
+
+use crate::color_space::ColorSpace;
+
+/// Bradford adaptation from D65 to the D50 illuminant ICC profiles connect through
+const D65_TO_D50: [[f64; 3]; 3] = [
+    [1.0478112, 0.0228866, -0.0501270],
+    [0.0295424, 0.9904844, -0.0170491],
+    [-0.0092345, 0.0150436, 0.7521316],
+];
+const D50: [f64; 3] = [0.9642, 1f64, 0.8249];
+
+fn png_error(err: png::EncodingError) -> ImageError {
+    return ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::Png), err));
+}
+
+/// Encodes an 8 or 16-bit RGBA image as a PNG tagged with `space`: sRGB gets an sRGB chunk, the
+/// linear spaces a gAMA of 1 and their primaries, and the others an embedded ICC profile, since
+/// gAMA can't describe their transfer functions.
+pub(crate) fn encode_png(image: &DynamicImage, space: ColorSpace) -> ImageResult<Vec<u8>> {
+    let (depth, data) = match image {
+        DynamicImage::ImageRgba8(image) => (BitDepth::Eight, image.as_raw().clone()),
+        // PNG samples are big-endian
+        DynamicImage::ImageRgba16(image) => (
+            BitDepth::Sixteen,
+            image.as_raw().iter().flat_map(|sample| sample.to_be_bytes()).collect(),
+        ),
+        _ => unreachable!("PNGs are written as 8 or 16-bit RGBA"),
+    };
+
+    let mut bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(depth);
+    if space == ColorSpace::Srgb {
+        encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+    } else {
+        let [white, red, green, blue] = chromaticities(space);
+        encoder.set_source_chromaticities(SourceChromaticities::new(white, red, green, blue));
+        if space.is_linear() {
+            encoder.set_source_gamma(ScaledFloat::new(1f32));
+        }
+    }
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    if let Some(profile) = icc_profile(space) {
+        let mut compressed = ZlibEncoder::new(vec![], Compression::default());
+        compressed.write_all(&profile)?;
+        let mut chunk = match space {
+            ColorSpace::DisplayP3 => b"Display P3\0\0".to_vec(),
+            _ => b"Rec. 2020\0\0".to_vec(),
+        };
+        chunk.append(&mut compressed.finish()?);
+        writer.write_chunk(png::chunk::iCCP, &chunk).map_err(png_error)?;
+    }
+    writer.write_image_data(&data).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    return Ok(bytes);
+}
+
+/// The xy chromaticities of the white point and the red, green and blue primaries.
+fn chromaticities(space: ColorSpace) -> [(f32, f32); 4] {
+    return [
+        [1f32, 1f32, 1f32],
+        [1f32, 0f32, 0f32],
+        [0f32, 1f32, 0f32],
+        [0f32, 0f32, 1f32],
+    ]
+    .map(|rgb| {
+        let (x, y, z) = space.linear_to_xyz(rgb).into_components();
+        (x / (x + y + z), y / (x + y + z))
+    });
+}
+
+/// Parameters (g, a, b, c, d) of the ICC type 3 parametric curve Y = (aX + b)^g for X >= d and
+/// Y = cX otherwise, matching the decoding side of the space's transfer function.
+fn transfer_curve(space: ColorSpace) -> [f64; 5] {
+    return match space {
+        ColorSpace::Rec2020 => {
+            let (alpha, beta) = (1.09929682680944f64, 0.018053968510807f64);
+            [
+                1f64 / 0.45f64,
+                1f64 / alpha,
+                1f64 - 1f64 / alpha,
+                1f64 / 4.5f64,
+                4.5f64 * beta,
+            ]
+        }
+        // Display P3 uses the sRGB curve
+        _ => [
+            2.4f64,
+            1f64 / 1.055f64,
+            0.055f64 / 1.055f64,
+            1f64 / 12.92f64,
+            0.04045f64,
+        ],
+    };
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    return ((value * 65536f64).round() as i32).to_be_bytes();
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in xyz {
+        tag.extend(s15_fixed16(value));
+    }
+    return tag;
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let utf16: Vec<u8> = text.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect();
+    let mut tag = b"mluc\0\0\0\0".to_vec();
+    tag.extend(1u32.to_be_bytes());
+    tag.extend(12u32.to_be_bytes());
+    tag.extend(b"enUS");
+    tag.extend((utf16.len() as u32).to_be_bytes());
+    tag.extend(28u32.to_be_bytes());
+    tag.extend(utf16);
+    return tag;
+}
+
+fn adapt(xyz: [f64; 3]) -> [f64; 3] {
+    return D65_TO_D50.map(|row| row[0] * xyz[0] + row[1] * xyz[1] + row[2] * xyz[2]);
+}
+
+/// A minimal ICC v4 display profile for the encoded wide-gamut spaces, or `None` for the ones the
+/// PNG chunks describe exactly.
+fn icc_profile(space: ColorSpace) -> Option<Vec<u8>> {
+    let description = match space {
+        ColorSpace::DisplayP3 => "Display P3",
+        ColorSpace::Rec2020 => "Rec. 2020",
+        _ => return None,
+    };
+
+    let colorants = [[1f32, 0f32, 0f32], [0f32, 1f32, 0f32], [0f32, 0f32, 1f32]].map(|rgb| {
+        let (x, y, z) = space.linear_to_xyz(rgb).into_components();
+        xyz_tag(adapt([x as f64, y as f64, z as f64]))
+    });
+    let [red, green, blue] = colorants;
+
+    let mut curve = b"para\0\0\0\0".to_vec();
+    curve.extend(3u16.to_be_bytes());
+    curve.extend([0u8; 2]);
+    for parameter in transfer_curve(space) {
+        curve.extend(s15_fixed16(parameter));
+    }
+
+    let mut adaptation = b"sf32\0\0\0\0".to_vec();
+    for row in D65_TO_D50 {
+        for value in row {
+            adaptation.extend(s15_fixed16(value));
+        }
+    }
+
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", text_tag(description)),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(D50)),
+        (b"chad", adaptation),
+        (b"rXYZ", red),
+        (b"gXYZ", green),
+        (b"bXYZ", blue),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    // Tag data goes after the header and the tag table, each element 4-byte aligned
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data: Vec<u8> = vec![];
+    let start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend(*signature);
+        table.extend(((start + data.len()) as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+        data.extend(tag);
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let mut header = vec![0u8; 128];
+    header[0..4].copy_from_slice(&((start + data.len()) as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    // A fixed creation date keeps the output reproducible
+    for (i, field) in [2023u16, 1, 1, 0, 0, 0].iter().enumerate() {
+        header[24 + 2 * i..26 + 2 * i].copy_from_slice(&field.to_be_bytes());
+    }
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, &value) in D50.iter().enumerate() {
+        header[68 + 4 * i..72 + 4 * i].copy_from_slice(&s15_fixed16(value));
+    }
+
+    let mut profile = header;
+    profile.append(&mut table);
+    profile.append(&mut data);
+    return Some(profile);
+}
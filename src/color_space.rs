@@ -0,0 +1,127 @@
+use image::Rgba32FImage;
+use palette::convert::FromColorUnclamped;
+use palette::rgb::{DisplayP3, LinDisplayP3};
+use palette::white_point::D65;
+use palette::{LinRec2020, LinSrgb, Rec2020, Srgb, Xyz};
+
+/// An RGB color space: a set of primaries and whether values are encoded with its transfer
+/// function. Rendering happens in a linear working space, so everything else is converted into it
+/// on the way in and out of it on the way out.
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum ColorSpace {
+    Srgb,
+    LinearSrgb,
+    DisplayP3,
+    LinearDisplayP3,
+    Rec2020,
+    LinearRec2020,
+}
+
+impl ColorSpace {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name.to_ascii_lowercase().as_str() {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" | "linear-srgb" => Some(ColorSpace::LinearSrgb),
+            "p3" | "displayp3" | "display-p3" => Some(ColorSpace::DisplayP3),
+            "linear-p3" | "linear-displayp3" | "linear-display-p3" => Some(ColorSpace::LinearDisplayP3),
+            "rec2020" => Some(ColorSpace::Rec2020),
+            "linear-rec2020" => Some(ColorSpace::LinearRec2020),
+            _ => None,
+        };
+    }
+
+    /// The linear space with the same primaries.
+    pub(crate) fn linear(self) -> Self {
+        return match self {
+            ColorSpace::Srgb | ColorSpace::LinearSrgb => ColorSpace::LinearSrgb,
+            ColorSpace::DisplayP3 | ColorSpace::LinearDisplayP3 => ColorSpace::LinearDisplayP3,
+            ColorSpace::Rec2020 | ColorSpace::LinearRec2020 => ColorSpace::LinearRec2020,
+        };
+    }
+
+    pub(crate) fn is_linear(self) -> bool {
+        return self == self.linear();
+    }
+
+    /// Applies the inverse transfer function, giving linear values with the same primaries.
+    fn decode(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        let linear = match self {
+            ColorSpace::Srgb => Srgb::new(r, g, b).into_linear::<f32>().into_components(),
+            ColorSpace::DisplayP3 => DisplayP3::new(r, g, b).into_linear::<f32>().into_components(),
+            ColorSpace::Rec2020 => Rec2020::new(r, g, b).into_linear::<f32>().into_components(),
+            _ => (r, g, b),
+        };
+        return linear.into();
+    }
+
+    /// Applies the transfer function to linear values with the same primaries.
+    fn encode(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        let encoded = match self {
+            ColorSpace::Srgb => Srgb::from_linear(LinSrgb::new(r, g, b)).into_components(),
+            ColorSpace::DisplayP3 => DisplayP3::from_linear(LinDisplayP3::new(r, g, b)).into_components(),
+            ColorSpace::Rec2020 => Rec2020::from_linear(LinRec2020::new(r, g, b)).into_components(),
+            _ => (r, g, b),
+        };
+        return encoded.into();
+    }
+
+    /// Linear values with this space's primaries, in XYZ.
+    pub(crate) fn linear_to_xyz(self, [r, g, b]: [f32; 3]) -> Xyz<D65, f32> {
+        return match self.linear() {
+            ColorSpace::LinearDisplayP3 => Xyz::from_color_unclamped(LinDisplayP3::new(r, g, b)),
+            ColorSpace::LinearRec2020 => Xyz::from_color_unclamped(LinRec2020::new(r, g, b)),
+            _ => Xyz::from_color_unclamped(LinSrgb::new(r, g, b)),
+        };
+    }
+
+    pub(crate) fn xyz_to_linear(self, xyz: Xyz<D65, f32>) -> [f32; 3] {
+        let linear = match self.linear() {
+            ColorSpace::LinearDisplayP3 => LinDisplayP3::from_color_unclamped(xyz).into_components(),
+            ColorSpace::LinearRec2020 => LinRec2020::from_color_unclamped(xyz).into_components(),
+            _ => LinSrgb::from_color_unclamped(xyz).into_components(),
+        };
+        return linear.into();
+    }
+}
+
+/// Converts `rgb` from one color space to another. Out-of-gamut results are left unclamped.
+pub(crate) fn convert(rgb: [f32; 3], from: ColorSpace, to: ColorSpace) -> [f32; 3] {
+    let linear = from.decode(rgb);
+    if from.linear() == to.linear() {
+        return to.encode(linear);
+    }
+    return to.encode(to.xyz_to_linear(from.linear_to_xyz(linear)));
+}
+
+/// The color spaces a script declares. Rendering and blending happen in `working`, which is always
+/// linear; textures and vertex colors are converted into it as they're loaded, and the resolved
+/// image is converted out of it into `output` when saved.
+#[derive(Copy, Clone)]
+pub(crate) struct ColorSpaces {
+    pub(crate) working: ColorSpace,
+    pub(crate) texture: ColorSpace,
+    pub(crate) vertex: ColorSpace,
+    pub(crate) output: ColorSpace,
+}
+
+impl ColorSpaces {
+    pub(crate) fn default() -> Self {
+        Self {
+            working: ColorSpace::LinearSrgb,
+            texture: ColorSpace::Srgb,
+            vertex: ColorSpace::LinearSrgb,
+            output: ColorSpace::LinearSrgb,
+        }
+    }
+}
+
+/// Converts every pixel of `image` in place, leaving alpha alone.
+pub(crate) fn convert_image(image: &mut Rgba32FImage, from: ColorSpace, to: ColorSpace) {
+    if from == to {
+        return;
+    }
+    for pixel in image.pixels_mut() {
+        let [r, g, b] = convert([pixel[0], pixel[1], pixel[2]], from, to);
+        [pixel[0], pixel[1], pixel[2]] = [r, g, b];
+    }
+}
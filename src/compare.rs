@@ -0,0 +1,420 @@
+use std::f32::consts::PI;
+use std::process::ExitCode;
+
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, Rgba, Rgba32FImage};
+use palette::Xyz;
+
+use crate::cli;
+use crate::color_space::{convert, ColorSpace};
+
+/// `rasterizer compare a.png b.png [heatmap.png]`: prints how far apart the two images are and writes
+/// a heatmap of where they differ, to `heatmap.png` by default.
+pub(crate) fn run(a: &str, b: &str, heatmap: Option<&str>) -> ExitCode {
+    let open = |filename: &str| -> Result<Rgba32FImage, &'static str> {
+        let image = ImageReader::open(filename).map_err(|_| "Unable to open image")?;
+        return Ok(image.decode().map_err(|_| "Unable to decode image")?.into_rgba32f());
+    };
+    let (a, b) = match (open(a), open(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            return ExitCode::from(cli::IO_ERROR);
+        }
+    };
+    let (metrics, heatmap_image) = match compare(&a, &b) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(cli::SCRIPT_ERROR);
+        }
+    };
+
+    println!(
+        "max abs error   {:.6} ({:.2}/255)",
+        metrics.max_error,
+        metrics.max_error * 255f32
+    );
+    println!(
+        "mean abs error  {:.6} ({:.2}/255)",
+        metrics.mean_error,
+        metrics.mean_error * 255f32
+    );
+    println!("PSNR            {:.2} dB", metrics.psnr);
+    println!("SSIM            {:.6}", metrics.ssim);
+    println!("FLIP            {:.6}", metrics.flip);
+
+    let heatmap = heatmap.unwrap_or("heatmap.png");
+    if DynamicImage::ImageRgba32F(heatmap_image)
+        .into_rgba8()
+        .save(heatmap)
+        .is_err()
+    {
+        eprintln!("Unable to write heatmap");
+        return ExitCode::from(cli::IO_ERROR);
+    }
+    return ExitCode::SUCCESS;
+}
+
+/// How far apart two images are, from plain per-channel error to perceptual estimates.
+pub(crate) struct Metrics {
+    /// Over all four channels, as fractions of the full range
+    pub(crate) max_error: f32,
+    pub(crate) mean_error: f32,
+    /// In decibels, infinite for identical images
+    pub(crate) psnr: f32,
+    /// Mean structural similarity of the luma, 1 for identical images
+    pub(crate) ssim: f32,
+    /// Mean FLIP-like perceptual error in [0, 1], 0 for identical images
+    pub(crate) flip: f32,
+}
+
+/// Compares two sRGB-encoded images of the same size, returning the metrics and a heatmap of the
+/// per-pixel FLIP error. SSIM and FLIP see the images composited over black, as they'd display.
+pub(crate) fn compare(a: &Rgba32FImage, b: &Rgba32FImage) -> Result<(Metrics, Rgba32FImage), &'static str> {
+    if a.dimensions() != b.dimensions() {
+        return Err("Images have different sizes");
+    }
+    if a.width() == 0 || a.height() == 0 {
+        return Err("Images are empty");
+    }
+
+    let mut max_error = 0f32;
+    let mut total_error = 0f64;
+    let mut total_squared = 0f64;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for c in 0..4 {
+            let error = (pa[c].clamp(0f32, 1f32) - pb[c].clamp(0f32, 1f32)).abs();
+            max_error = max_error.max(error);
+            total_error += error as f64;
+            total_squared += (error * error) as f64;
+        }
+    }
+    let samples = (a.width() * a.height() * 4) as f64;
+    let mse = total_squared / samples;
+    let psnr = if mse == 0f64 {
+        f32::INFINITY
+    } else {
+        (-10f64 * mse.log10()) as f32
+    };
+
+    let (ca, cb) = (Plane::composite(a), Plane::composite(b));
+    let ssim = ssim(&ca, &cb);
+    let errors = flip(&ca, &cb);
+    let flip = (errors.iter().map(|&e| e as f64).sum::<f64>() / errors.len() as f64) as f32;
+
+    let mut heatmap = Rgba32FImage::new(a.width(), a.height());
+    for (pixel, &error) in heatmap.pixels_mut().zip(&errors) {
+        *pixel = heat(error);
+    }
+
+    let metrics = Metrics {
+        max_error,
+        mean_error: (total_error / samples) as f32,
+        psnr,
+        ssim,
+        flip,
+    };
+    return Ok((metrics, heatmap));
+}
+
+/// One value per pixel, row by row.
+struct Plane {
+    width: usize,
+    height: usize,
+    values: Vec<[f32; 3]>,
+}
+
+impl Plane {
+    /// The sRGB-encoded colors of `image` over black.
+    fn composite(image: &Rgba32FImage) -> Self {
+        let values = image
+            .pixels()
+            .map(|p| {
+                let alpha = p[3].clamp(0f32, 1f32);
+                [p[0], p[1], p[2]].map(|c| c.clamp(0f32, 1f32) * alpha)
+            })
+            .collect();
+        Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            values,
+        }
+    }
+
+    fn map(&self, f: impl Fn([f32; 3]) -> [f32; 3]) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            values: self.values.iter().map(|&v| f(v)).collect(),
+        }
+    }
+
+    /// Convolves each channel with its own separable kernel, given as odd-length 1D kernels along x
+    /// and y, clamping at the edges.
+    fn convolve(&self, along_x: [&[f32]; 3], along_y: [&[f32]; 3]) -> Self {
+        let pass = |values: &[[f32; 3]], kernels: [&[f32]; 3], step: [usize; 2]| {
+            let mut result = vec![[0f32; 3]; values.len()];
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    for c in 0..3 {
+                        let r = (kernels[c].len() / 2) as i64;
+                        let mut sum = 0f32;
+                        for (k, &weight) in kernels[c].iter().enumerate() {
+                            let offset = k as i64 - r;
+                            let sx = (x as i64 + offset * step[0] as i64).clamp(0, self.width as i64 - 1) as usize;
+                            let sy = (y as i64 + offset * step[1] as i64).clamp(0, self.height as i64 - 1) as usize;
+                            sum += weight * values[sy * self.width + sx][c];
+                        }
+                        result[y * self.width + x][c] = sum;
+                    }
+                }
+            }
+            result
+        };
+        let values = pass(&pass(&self.values, along_x, [1, 0]), along_y, [0, 1]);
+        Self {
+            width: self.width,
+            height: self.height,
+            values,
+        }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            values: self
+                .values
+                .iter()
+                .zip(&other.values)
+                .map(|(a, b)| std::array::from_fn(|c| a[c] + b[c]))
+                .collect(),
+        }
+    }
+}
+
+/// Samples of `f` at whole pixel offsets out to `radius`.
+fn kernel(radius: usize, f: impl Fn(f32) -> f32) -> Vec<f32> {
+    let r = radius as i64;
+    return (-r..=r).map(|x| f(x as f32)).collect();
+}
+
+/// A normalized 1D Gaussian kernel.
+fn gaussian(sigma: f32) -> Vec<f32> {
+    let radius = (3f32 * sigma).ceil().max(1f32) as usize;
+    let weights = kernel(radius, |x| (-x * x / (2f32 * sigma * sigma)).exp());
+    let total: f32 = weights.iter().sum();
+    return weights.iter().map(|w| w / total).collect();
+}
+
+fn luma([r, g, b]: [f32; 3]) -> f32 {
+    return 0.2126f32 * r + 0.7152f32 * g + 0.0722f32 * b;
+}
+
+/// Mean SSIM over the luma, with the usual 1.5 pixel Gaussian window and constants.
+fn ssim(a: &Plane, b: &Plane) -> f32 {
+    let (c1, c2) = (0.01f32.powi(2), 0.03f32.powi(2));
+    // Luma, its square and the cross term of both images, blurred together
+    let products = Plane {
+        width: a.width,
+        height: a.height,
+        values: a
+            .values
+            .iter()
+            .zip(&b.values)
+            .map(|(&va, &vb)| [luma(va), luma(vb), luma(va) * luma(vb)])
+            .collect(),
+    };
+    let squares = products.map(|[ya, yb, _]| [ya * ya, yb * yb, 0f32]);
+    let window = gaussian(1.5f32);
+    let means = products.convolve([&window; 3], [&window; 3]);
+    let moments = squares.convolve([&window; 3], [&window; 3]);
+
+    let mut total = 0f64;
+    for (&[mu_a, mu_b, mu_ab], &[sq_a, sq_b, _]) in means.values.iter().zip(&moments.values) {
+        let var_a = sq_a - mu_a * mu_a;
+        let var_b = sq_b - mu_b * mu_b;
+        let covariance = mu_ab - mu_a * mu_b;
+        let value = (2f32 * mu_a * mu_b + c1) * (2f32 * covariance + c2)
+            / ((mu_a * mu_a + mu_b * mu_b + c1) * (var_a + var_b + c2));
+        total += value as f64;
+    }
+    return (total / means.values.len() as f64) as f32;
+}
+
+/// Pixels per degree of visual angle for a 0.7 m viewing distance from a 4K 27" monitor, the setup
+/// FLIP is tuned for.
+const PIXELS_PER_DEGREE: f32 = 67f32;
+
+/// FLIP's contrast sensitivity functions for the achromatic, red-green and blue-yellow channels, as
+/// sums of two Gaussians (a1, b1, a2, b2) with b in square degrees.
+const CSF: [[f32; 4]; 3] = [
+    [1f32, 0.0047f32, 0f32, 1e-5f32],
+    [1f32, 0.0053f32, 0f32, 1e-5f32],
+    [34.1f32, 0.04f32, 13.5f32, 0.025f32],
+];
+
+fn white() -> [f32; 3] {
+    let (x, y, z) = ColorSpace::LinearSrgb.linear_to_xyz([1f32; 3]).into_components();
+    return [x, y, z];
+}
+
+fn to_ycxcz(rgb: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = ColorSpace::LinearSrgb
+        .linear_to_xyz(convert(rgb, ColorSpace::Srgb, ColorSpace::LinearSrgb))
+        .into_components();
+    let [xn, yn, zn] = white();
+    return [
+        116f32 * y / yn - 16f32,
+        500f32 * (x / xn - y / yn),
+        200f32 * (y / yn - z / zn),
+    ];
+}
+
+/// Back from YCxCz to linear sRGB, clamped to the gamut.
+fn from_ycxcz([yy, cx, cz]: [f32; 3]) -> [f32; 3] {
+    let [xn, yn, zn] = white();
+    let y = (yy + 16f32) / 116f32;
+    let x = cx / 500f32 + y;
+    let z = y - cz / 200f32;
+    let xyz = Xyz::new(x * xn, y * yn, z * zn);
+    return ColorSpace::LinearSrgb.xyz_to_linear(xyz).map(|c| c.clamp(0f32, 1f32));
+}
+
+/// CIELAB with FLIP's Hunt adjustment, which scales chroma down with lightness.
+fn to_hunt_lab(linear: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = ColorSpace::LinearSrgb.linear_to_xyz(linear).into_components();
+    let [xn, yn, zn] = white();
+    let f = |t: f32| {
+        if t > (6f32 / 29f32).powi(3) {
+            t.cbrt()
+        } else {
+            t / (3f32 * (6f32 / 29f32).powi(2)) + 4f32 / 29f32
+        }
+    };
+    let (fx, fy, fz) = (f(x / xn), f(y / yn), f(z / zn));
+    let l = 116f32 * fy - 16f32;
+    return [l, 0.01f32 * l * 500f32 * (fx - fy), 0.01f32 * l * 200f32 * (fy - fz)];
+}
+
+fn hyab(a: [f32; 3], b: [f32; 3]) -> f32 {
+    return (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt();
+}
+
+/// The CSF filters as the 1D kernels of their two Gaussian terms, scaled so each channel's 2D
+/// filter sums to 1.
+fn csf_kernels() -> [[Vec<f32>; 3]; 2] {
+    let widest = CSF.iter().map(|p| p[1].max(p[3])).fold(0f32, f32::max);
+    let radius = (3f32 * (widest / (2f32 * PI * PI)).sqrt() * PIXELS_PER_DEGREE).ceil() as usize;
+    // A 2D term a * (π / b) * exp(-π² d² / b) is the product of two of these along x and y
+    let term = |b: f32| {
+        kernel(radius, |x| {
+            (PI / b).sqrt() * (-PI * PI * (x / PIXELS_PER_DEGREE).powi(2) / b).exp()
+        })
+    };
+    let kernels = CSF.map(|[a1, b1, a2, b2]| {
+        let (g1, g2) = (term(b1), term(b2));
+        let total = a1 * g1.iter().sum::<f32>().powi(2) + a2 * g2.iter().sum::<f32>().powi(2);
+        let scale = |a: f32, g: Vec<f32>| g.iter().map(|w| w * (a / total).sqrt()).collect::<Vec<f32>>();
+        [scale(a1, g1), scale(a2, g2)]
+    });
+    return [0, 1].map(|t| kernels.each_ref().map(|k| k[t].clone()));
+}
+
+/// Edge and point detection along x, as the first and second derivatives of a Gaussian across
+/// and the Gaussian itself along. The derivatives' positive and negative weights each sum to 1 in
+/// magnitude, and the Gaussian to 1.
+fn feature_kernels() -> [Vec<f32>; 3] {
+    let sigma = 0.5f32 * 0.082f32 * PIXELS_PER_DEGREE;
+    let radius = (3f32 * sigma).ceil() as usize;
+    let g = |x: f32| (-x * x / (2f32 * sigma * sigma)).exp();
+    let normalize = |weights: Vec<f32>| {
+        let positive: f32 = weights.iter().filter(|&&w| w > 0f32).sum();
+        let negative: f32 = -weights.iter().filter(|&&w| w < 0f32).sum::<f32>();
+        weights
+            .iter()
+            .map(|&w| if w > 0f32 { w / positive } else { w / negative })
+            .collect::<Vec<f32>>()
+    };
+    let edge = normalize(kernel(radius, |x| -x * g(x)));
+    let point = normalize(kernel(radius, |x| (x * x / (sigma * sigma) - 1f32) * g(x)));
+    let along = kernel(radius, g);
+    let total: f32 = along.iter().sum();
+    return [edge, point, along.iter().map(|w| w / total).collect()];
+}
+
+/// Edge and point strength at each pixel of a plane holding luminance in every channel.
+fn features(plane: &Plane, [edge, point, along]: &[Vec<f32>; 3]) -> Vec<[f32; 2]> {
+    let along_x = plane.convolve([edge, point, edge], [along; 3]);
+    let along_y = plane.convolve([along; 3], [edge, point, edge]);
+    return along_x
+        .values
+        .iter()
+        .zip(&along_y.values)
+        .map(|(&[ex, px, _], &[ey, py, _])| [(ex * ex + ey * ey).sqrt(), (px * px + py * py).sqrt()])
+        .collect();
+}
+
+/// Per-pixel error in the spirit of NVIDIA's FLIP: a color difference between the images after
+/// filtering by the eye's contrast sensitivity, amplified where edges or points differ.
+fn flip(a: &Plane, b: &Plane) -> Vec<f32> {
+    let (pc, pt) = (0.4f32, 0.95f32);
+    let (qc, qf) = (0.7f32, 0.5f32);
+    // Green to blue is the largest Hunt-adjusted difference in the sRGB gamut
+    let cmax = hyab(to_hunt_lab([0f32, 1f32, 0f32]), to_hunt_lab([0f32, 0f32, 1f32])).powf(qc);
+
+    let [first, second] = csf_kernels();
+    let filter = |plane: &Plane| {
+        let opponent = plane.map(to_ycxcz);
+        let [f0, f1, f2] = first.each_ref().map(|k| k.as_slice());
+        let [s0, s1, s2] = second.each_ref().map(|k| k.as_slice());
+        opponent
+            .convolve([f0, f1, f2], [f0, f1, f2])
+            .add(&opponent.convolve([s0, s1, s2], [s0, s1, s2]))
+            .map(|v| to_hunt_lab(from_ycxcz(v)))
+    };
+    let (la, lb) = (filter(a), filter(b));
+
+    let feature_kernels = feature_kernels();
+    let luminance = |plane: &Plane| {
+        plane.map(|rgb| {
+            let lightness = to_hunt_lab(convert(rgb, ColorSpace::Srgb, ColorSpace::LinearSrgb))[0];
+            [lightness / 100f32; 3]
+        })
+    };
+    let fa = features(&luminance(a), &feature_kernels);
+    let fb = features(&luminance(b), &feature_kernels);
+
+    let mut errors = vec![];
+    for i in 0..la.values.len() {
+        let color = hyab(la.values[i], lb.values[i]).powf(qc);
+        // Compresses large differences into the top of the range
+        let color = if color < pc * cmax {
+            pt / (pc * cmax) * color
+        } else {
+            pt + (color - pc * cmax) / (cmax - pc * cmax) * (1f32 - pt)
+        };
+        let edge = (fa[i][0] - fb[i][0]).abs();
+        let point = (fa[i][1] - fb[i][1]).abs();
+        let feature = (edge.max(point) / 2f32.sqrt()).powf(qf);
+        errors.push(color.clamp(0f32, 1f32).powf(1f32 - feature));
+    }
+    return errors;
+}
+
+/// Maps an error in [0, 1] onto a black, purple, orange and yellow ramp like matplotlib's inferno.
+fn heat(error: f32) -> Rgba<f32> {
+    const STOPS: [[f32; 3]; 5] = [
+        [0f32, 0f32, 0.016f32],
+        [0.341f32, 0.063f32, 0.431f32],
+        [0.737f32, 0.216f32, 0.329f32],
+        [0.976f32, 0.557f32, 0.035f32],
+        [0.988f32, 1f32, 0.643f32],
+    ];
+    let position = error.clamp(0f32, 1f32) * (STOPS.len() - 1) as f32;
+    let i = (position.floor() as usize).min(STOPS.len() - 2);
+    let t = position - i as f32;
+    let [r, g, b] = std::array::from_fn(|c| STOPS[i][c] + (STOPS[i + 1][c] - STOPS[i][c]) * t);
+    return Rgba([r, g, b, 1f32]);
+}
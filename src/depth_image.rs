@@ -1,6 +1,144 @@
-use image::{ImageResult, Rgba, Rgba32FImage, RgbaImage};
-use palette::rgb::Rgb;
-use palette::Srgb;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use image::{
+    DynamicImage, ImageBuffer, ImageFormat, ImageOutputFormat, ImageResult, Luma, Rgba, Rgba32FImage, RgbaImage,
+};
+
+use crate::color_profile::encode_png;
+use crate::color_space::{convert, ColorSpace, ColorSpaces};
+use crate::quantize::{quantize, Dither};
+use crate::sampling::{ResolveFilter, SamplePattern, Sampling};
+use crate::tonemap::ToneMap;
+
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum OutputFormat {
+    Png,
+    Png16,
+    Exr,
+    Ppm,
+    Pam,
+    Bmp,
+    Tga,
+    Qoi,
+    WebP,
+}
+
+impl OutputFormat {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "png16" => Some(OutputFormat::Png16),
+            "exr" => Some(OutputFormat::Exr),
+            "ppm" => Some(OutputFormat::Ppm),
+            "pam" => Some(OutputFormat::Pam),
+            "bmp" => Some(OutputFormat::Bmp),
+            "tga" => Some(OutputFormat::Tga),
+            "qoi" => Some(OutputFormat::Qoi),
+            "webp" => Some(OutputFormat::WebP),
+            _ => None,
+        };
+    }
+
+    /// The format written by one of the shorthand output commands. Plain `png` also honours an
+    /// `.exr` extension.
+    pub(crate) fn from_command(command: &str, path: &str) -> Option<Self> {
+        let is_exr = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+
+        return match command {
+            "png" if is_exr => Some(OutputFormat::Exr),
+            "png" | "png16" | "exr" => Self::from_name(command),
+            _ => None,
+        };
+    }
+
+    /// The format for writing to `path` instead, going by its extension. 16-bit PNG stays 16-bit, and
+    /// an extension naming no format keeps this one.
+    pub(crate) fn with_extension_of(self, path: &str) -> Self {
+        let named = Path::new(path)
+            .extension()
+            .and_then(|extension| Self::from_name(&extension.to_string_lossy()));
+        return match named {
+            Some(OutputFormat::Png) if self == OutputFormat::Png16 => self,
+            Some(format) => format,
+            None => self,
+        };
+    }
+
+    fn encoder(self) -> ImageOutputFormat {
+        return match self {
+            OutputFormat::Png | OutputFormat::Png16 => ImageOutputFormat::Png,
+            OutputFormat::Exr => ImageOutputFormat::OpenExr,
+            OutputFormat::Ppm => ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+            OutputFormat::Pam => ImageOutputFormat::Pnm(PnmSubtype::ArbitraryMap),
+            OutputFormat::Bmp => ImageOutputFormat::Bmp,
+            OutputFormat::Tga => ImageOutputFormat::Tga,
+            OutputFormat::Qoi => ImageOutputFormat::Qoi,
+            #[cfg(feature = "webp")]
+            OutputFormat::WebP => ImageOutputFormat::WebP,
+            #[cfg(not(feature = "webp"))]
+            OutputFormat::WebP => ImageOutputFormat::Unsupported(String::from("WebP (build with the webp feature)")),
+        };
+    }
+}
+
+/// How the FSAA samples of a pixel combine into one depth value.
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum DepthResolve {
+    Min,
+    Max,
+    Average,
+}
+
+impl DepthResolve {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "min" => Some(DepthResolve::Min),
+            "max" => Some(DepthResolve::Max),
+            "average" | "avg" => Some(DepthResolve::Average),
+            _ => None,
+        };
+    }
+}
+
+/// How resolved colors are finished into output pixels.
+#[derive(Copy, Clone)]
+pub(crate) struct OutputOptions {
+    pub(crate) dither: Dither,
+    pub(crate) tone_map: ToneMap,
+    pub(crate) premultiplied: bool,
+}
+
+impl OutputOptions {
+    pub(crate) fn default() -> Self {
+        Self {
+            dither: Dither::None,
+            tone_map: ToneMap::default(),
+            premultiplied: false,
+        }
+    }
+}
+
+/// Turns a resolved straight-alpha color into an output pixel. Premultiplying comes last, so it
+/// applies to the encoded values as viewers expect.
+fn finish(rgb: [f32; 3], alpha: f32, working: ColorSpace, output: ColorSpace, options: OutputOptions) -> Rgba<f32> {
+    let mut result = rgb;
+    if !options.tone_map.is_identity() {
+        result = options.tone_map.apply(result);
+    }
+    if working != output {
+        result = convert(result, working, output);
+    }
+    if options.premultiplied {
+        result = result.map(|c| c * alpha);
+    }
+    let [r, g, b] = result;
+    return Rgba([r, g, b, alpha]);
+}
 
 pub(crate) struct DepthImage {
     data: Rgba32FImage,
@@ -8,7 +146,7 @@ pub(crate) struct DepthImage {
     frame_buf: Vec<Rgba<f32>>,
     width: u32,
     height: u32,
-    fsaa: u32,
+    sampling: Sampling,
 }
 
 impl DepthImage {
@@ -23,31 +161,80 @@ impl DepthImage {
             frame_buf,
             width: 0,
             height: 0,
-            fsaa: 1,
+            sampling: Sampling::default(),
         }
     }
 
-    pub(crate) fn from_pixel(width: u32, height: u32, pixel: Rgba<f32>, fsaa: u32) -> Self {
+    /// Fails if the samples can't all be addressed, since sample coordinates are computed in `u32`.
+    pub(crate) fn from_pixel(
+        width: u32,
+        height: u32,
+        pixel: Rgba<f32>,
+        sampling: Sampling,
+    ) -> Result<Self, &'static str> {
+        let samples = width
+            .checked_mul(sampling.fsaa)
+            .zip(height.checked_mul(sampling.fsaa))
+            .and_then(|(width, height)| width.checked_mul(height));
+        let Some(samples) = samples else {
+            return Err("Image is too large for its FSAA level");
+        };
+
         let data = Rgba32FImage::from_pixel(width, height, pixel);
-        let depth_buf = vec![None; (width * height * fsaa.pow(2)) as usize];
-        let frame_buf = vec![Rgba([0f32; 4]); (width * height * fsaa.pow(2)) as usize];
+        let depth_buf = vec![None; samples as usize];
+        let frame_buf = vec![Rgba([0f32; 4]); samples as usize];
 
-        Self {
+        Ok(Self {
             data,
             depth_buf,
             frame_buf,
             width,
             height,
-            fsaa,
+            sampling,
+        })
+    }
+
+    /// Changes the sampling, keeping what has been drawn: each new sample takes the color and depth
+    /// of the old sample in the same position of the same pixel's cell grid, rounded down.
+    pub(crate) fn resample(&mut self, sampling: Sampling) -> Result<(), &'static str> {
+        let mut result = Self::from_pixel(self.width, self.height, Rgba([0f32; 4]), sampling)?;
+        let (old, new) = (self.sampling.fsaa, sampling.fsaa);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for j in 0..new {
+                    for i in 0..new {
+                        let from = ((y * old + j * old / new) * self.width() + x * old + i * old / new) as usize;
+                        let to = ((y * new + j) * result.width() + x * new + i) as usize;
+                        result.frame_buf[to] = self.frame_buf[from];
+                        result.depth_buf[to] = self.depth_buf[from];
+                    }
+                }
+            }
         }
+
+        *self = result;
+        Ok(())
+    }
+
+    pub(crate) fn sampling(&self) -> Sampling {
+        return self.sampling;
+    }
+
+    pub(crate) fn set_pattern(&mut self, pattern: SamplePattern) {
+        self.sampling.pattern = pattern;
+    }
+
+    pub(crate) fn set_filter(&mut self, filter: ResolveFilter) {
+        self.sampling.filter = filter;
     }
 
     pub(crate) fn width(&self) -> u32 {
-        self.data.width() * self.fsaa
+        self.data.width() * self.sampling.fsaa
     }
 
     pub(crate) fn height(&self) -> u32 {
-        self.data.height() * self.fsaa
+        self.data.height() * self.sampling.fsaa
     }
 
     pub(crate) fn depth(&self, x: u32, y: u32) -> f64 {
@@ -63,26 +250,38 @@ impl DepthImage {
         }
     }
 
+    pub(crate) fn put_depth(&mut self, x: u32, y: u32, depth: f64) {
+        let coord = (y * self.width() + x) as usize;
+        self.depth_buf[coord] = Some(depth);
+    }
+
     pub(crate) fn get_pixel(&self, x: u32, y: u32) -> Rgba<f32> {
         let coord = (y * self.width() + x) as usize;
         return self.frame_buf[coord];
     }
 
-    fn save_data(&mut self, s_rgb: bool) {
+    /// Resolves the premultiplied samples into `data`, tone mapping them and converting from the
+    /// `working` space to `output`.
+    fn save_data(&mut self, working: ColorSpace, output: ColorSpace, options: OutputOptions) {
+        if self.sampling.filter != ResolveFilter::Box {
+            self.save_filtered_data(working, output, options);
+            return;
+        }
+
         for x in 0..self.width {
             for y in 0..self.height {
                 let mut avg_r = 0f32;
                 let mut avg_g = 0f32;
                 let mut avg_b = 0f32;
                 let mut divisor = 0f32;
-                for i in 0..self.fsaa {
-                    for j in 0..self.fsaa {
-                        let coord = ((y * self.fsaa + j) * self.width() + x * self.fsaa + i) as usize;
+                for i in 0..self.sampling.fsaa {
+                    for j in 0..self.sampling.fsaa {
+                        let coord = ((y * self.sampling.fsaa + j) * self.width() + x * self.sampling.fsaa + i) as usize;
                         let pixel = self.frame_buf[coord];
 
-                        avg_r += pixel[0] * pixel[3];
-                        avg_g += pixel[1] * pixel[3];
-                        avg_b += pixel[2] * pixel[3];
+                        avg_r += pixel[0];
+                        avg_g += pixel[1];
+                        avg_b += pixel[2];
                         divisor += pixel[3];
                     }
                 }
@@ -92,33 +291,236 @@ impl DepthImage {
                     avg_g /= divisor;
                     avg_b /= divisor;
                 }
-                let avg_a = divisor / self.fsaa.pow(2) as f32;
+                let avg_a = divisor / self.sampling.fsaa.pow(2) as f32;
+
+                let pixel = finish([avg_r, avg_g, avg_b], avg_a, working, output, options);
+                self.data.put_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    /// Like `save_data`, but weighting every sample within the filter's radius of a pixel's center
+    /// rather than averaging only the pixel's own samples.
+    fn save_filtered_data(&mut self, working: ColorSpace, output: ColorSpace, options: OutputOptions) {
+        let Sampling {
+            fsaa, pattern, filter, ..
+        } = self.sampling;
+        let positions: Vec<(u32, u32, [f64; 2])> = (0..fsaa)
+            .flat_map(|j| (0..fsaa).map(move |i| (i, j, pattern.position(fsaa, i, j))))
+            .collect();
+        let center = pattern.center(fsaa);
+        let reach = filter.radius().ceil() as i64;
 
-                if s_rgb {
-                    let s_rgb_pixel = Srgb::<f32>::from_linear(Rgb::from_components((avg_r, avg_g, avg_b)));
-                    avg_r = s_rgb_pixel.red;
-                    avg_g = s_rgb_pixel.green;
-                    avg_b = s_rgb_pixel.blue;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut color = [0f64; 3];
+                let mut alpha = 0f64;
+                let mut weights = 0f64;
+                for n_y in (y as i64 - reach)..=(y as i64 + reach) {
+                    for n_x in (x as i64 - reach)..=(x as i64 + reach) {
+                        if n_x < 0 || n_y < 0 || n_x >= self.width as i64 || n_y >= self.height as i64 {
+                            continue;
+                        }
+
+                        for &(i, j, [s_x, s_y]) in &positions {
+                            let d_x = (n_x - x as i64) as f64 + s_x - center[0];
+                            let d_y = (n_y - y as i64) as f64 + s_y - center[1];
+                            let weight = filter.weight(d_x, d_y);
+                            if weight == 0f64 {
+                                continue;
+                            }
+
+                            let coord = ((n_y as u32 * fsaa + j) * self.width() + n_x as u32 * fsaa + i) as usize;
+                            let pixel = self.frame_buf[coord];
+                            for (sum, &value) in color.iter_mut().zip(&pixel.0[0..3]) {
+                                *sum += weight * value as f64;
+                            }
+                            alpha += weight * pixel[3] as f64;
+                            weights += weight;
+                        }
+                    }
                 }
 
-                self.data.put_pixel(x, y, Rgba([avg_r, avg_g, avg_b, avg_a]));
+                // Negative lobes can ring below zero, or leave no positive coverage at all
+                if alpha > 0f64 {
+                    color = color.map(|sum| (sum / alpha).max(0f64));
+                } else {
+                    color = [0f64; 3];
+                }
+                let a = if weights != 0f64 {
+                    (alpha / weights).clamp(0f64, 1f64) as f32
+                } else {
+                    0f32
+                };
+
+                let pixel = finish(color.map(|c| c as f32), a, working, output, options);
+                self.data.put_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    /// The resolved image in the working space, for use as a texture.
+    pub(crate) fn resolve(&mut self, working: ColorSpace) -> Rgba32FImage {
+        self.save_data(working, working, OutputOptions::default());
+        return self.data.clone();
+    }
+
+    /// Quantizes every channel of the resolved image to an integer in [0, max].
+    fn quantized_data(&self, max: f32, dither: Dither) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.data.as_raw().len());
+        for (x, y, pixel) in self.data.enumerate_pixels() {
+            for (channel, &value) in pixel.0.iter().enumerate() {
+                result.push(quantize(value, max, x, y, channel, dither));
             }
         }
+        return result;
     }
 
-    pub(crate) fn save(&mut self, path: String, s_rgb: bool) -> ImageResult<()> {
-        self.save_data(s_rgb);
-        let temp: RgbaImage = RgbaImage::from_vec(
-            self.width,
-            self.height,
-            self.data
-                .clone()
-                .into_vec()
-                .iter()
-                .map(|&a| (a * 255f32) as u8)
-                .collect(),
-        )
-        .unwrap();
-        temp.save(path)
+    /// Resolves and encodes the image, writing it to `path`, or to stdout if `path` is `-`.
+    pub(crate) fn save(
+        &mut self,
+        path: String,
+        format: OutputFormat,
+        colors: ColorSpaces,
+        options: OutputOptions,
+    ) -> ImageResult<()> {
+        let image: DynamicImage = match format {
+            OutputFormat::Exr => {
+                // EXR holds scene-referred linear values as they are, so no tone mapping, transfer
+                // function or clamping
+                let options = OutputOptions {
+                    tone_map: ToneMap::default(),
+                    ..options
+                };
+                self.save_data(colors.working, colors.output.linear(), options);
+                DynamicImage::ImageRgba32F(self.data.clone())
+            }
+            OutputFormat::Png16 => {
+                self.save_data(colors.working, colors.output, options);
+                let temp: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_vec(
+                    self.width,
+                    self.height,
+                    self.quantized_data(65535f32, options.dither)
+                        .iter()
+                        .map(|&a| a as u16)
+                        .collect(),
+                )
+                .unwrap();
+                DynamicImage::ImageRgba16(temp)
+            }
+            _ => {
+                self.save_data(colors.working, colors.output, options);
+                let temp: RgbaImage = RgbaImage::from_vec(
+                    self.width,
+                    self.height,
+                    self.quantized_data(255f32, options.dither)
+                        .iter()
+                        .map(|&a| a as u8)
+                        .collect(),
+                )
+                .unwrap();
+                if format == OutputFormat::Ppm {
+                    // PPM has no alpha channel
+                    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(temp).to_rgb8())
+                } else {
+                    DynamicImage::ImageRgba8(temp)
+                }
+            }
+        };
+
+        let bytes = if matches!(format, OutputFormat::Png | OutputFormat::Png16) {
+            encode_png(&image, colors.output)?
+        } else {
+            let mut bytes = Cursor::new(vec![]);
+            image.write_to(&mut bytes, format.encoder())?;
+            bytes.into_inner()
+        };
+        if path == "-" {
+            io::stdout().write_all(&bytes)?;
+        } else {
+            fs::write(path, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Resolved depth and the fraction of samples with a depth for each pixel, row by row. Pixels
+    /// with no depth at all get `far`.
+    fn resolve_depth(&self, resolve: DepthResolve, far: f64) -> Vec<(f64, f32)> {
+        let mut result = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut samples: Vec<f64> = vec![];
+                for j in 0..self.sampling.fsaa {
+                    for i in 0..self.sampling.fsaa {
+                        let coord = ((y * self.sampling.fsaa + j) * self.width() + x * self.sampling.fsaa + i) as usize;
+                        if let Some(depth) = self.depth_buf[coord] {
+                            samples.push(depth);
+                        }
+                    }
+                }
+
+                let coverage = samples.len() as f32 / self.sampling.fsaa.pow(2) as f32;
+                let depth = if samples.is_empty() {
+                    far
+                } else {
+                    match resolve {
+                        DepthResolve::Min => samples.iter().copied().fold(f64::INFINITY, f64::min),
+                        DepthResolve::Max => samples.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                        DepthResolve::Average => samples.iter().sum::<f64>() / samples.len() as f64,
+                    }
+                };
+                result.push((depth, coverage));
+            }
+        }
+        return result;
+    }
+
+    /// Writes the depth buffer, picking the format from the extension: a PNG is 16-bit grayscale
+    /// stretched from the nearest to the farthest depth, an EXR has the raw depth in its color
+    /// channels and the coverage in alpha, and anything else gets raw little-endian f32s.
+    pub(crate) fn save_depth(&self, path: &str, resolve: DepthResolve, far: f64) -> ImageResult<()> {
+        let depths = self.resolve_depth(resolve, far);
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_ref().and_then(|extension| extension.to_str()) {
+            Some("png") => {
+                let near = depths.iter().map(|&(depth, _)| depth).fold(f64::INFINITY, f64::min);
+                let farthest = depths.iter().map(|&(depth, _)| depth).fold(f64::NEG_INFINITY, f64::max);
+                let range = if farthest > near { farthest - near } else { 1f64 };
+
+                let temp: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_vec(
+                    self.width,
+                    self.height,
+                    depths
+                        .iter()
+                        .map(|&(depth, _)| (((depth - near) / range).clamp(0f64, 1f64) * 65535f64).round() as u16)
+                        .collect(),
+                )
+                .unwrap();
+                temp.save_with_format(path, ImageFormat::Png)
+            }
+            Some("exr") => {
+                let temp: Rgba32FImage = Rgba32FImage::from_vec(
+                    self.width,
+                    self.height,
+                    depths
+                        .iter()
+                        .flat_map(|&(depth, coverage)| [depth as f32, depth as f32, depth as f32, coverage])
+                        .collect(),
+                )
+                .unwrap();
+                temp.save_with_format(path, ImageFormat::OpenExr)
+            }
+            _ => {
+                let bytes: Vec<u8> = depths
+                    .iter()
+                    .flat_map(|&(depth, _)| (depth as f32).to_le_bytes())
+                    .collect();
+                fs::write(path, bytes)?;
+                Ok(())
+            }
+        }
     }
 }
@@ -1,118 +1,280 @@
 use image::{Rgba, Rgba32FImage};
-use ndarray::Array2;
-use palette::Srgb;
 
-use crate::axis::axis::{A, B, G, R, S, T, X, Y, Z};
+use crate::axis::axis::{A, B, G, LW, LX, LY, LZ, NX, NY, NZ, R, S, T, TW, TX, TY, TZ, W, X, Y, Z};
 use crate::depth_image::DepthImage;
+use crate::lighting::{fill_tangent_frame, transform_tangent_frame};
 use crate::point::{Point, Points};
-use crate::rasterize::{square, triangle};
+use crate::rasterize::{quad, square, square_corners, triangle};
+use crate::render_state::RenderState;
+use crate::sampling::{SamplePattern, Sampling};
 
-fn overlay_pixels(cur_pixel: Rgba<f32>, pixel: Rgba<f32>) -> [f32; 4] {
-    let [r_s, g_s, b_s, a_s] = pixel.0;
-    let [r_d, g_d, b_d, a_d] = cur_pixel.0;
+/// The attributes of a triangle that are interpolated perspective-correctly in hyperbolic mode.
+const VARYING: [usize; 18] = [Z, R, G, B, A, S, T, NX, NY, NZ, TX, TY, TZ, TW, LX, LY, LZ, LW];
 
-    let a = a_s + a_d * (1f32 - a_s);
-    let r = a_s / a * r_s + (1f32 - a_s) * a_d / a * r_d;
-    let g = a_s / a * g_s + (1f32 - a_s) * a_d / a * g_d;
-    let b = a_s / a * b_s + (1f32 - a_s) * a_d / a * b_d;
+fn premultiply(pixel: Rgba<f32>) -> Rgba<f32> {
+    let [r, g, b, a] = pixel.0;
+    return Rgba([r * a, g * a, b * a, a]);
+}
+
+/// Composites `pixel` over `cur_pixel`, both premultiplied.
+fn overlay_pixels(cur_pixel: Rgba<f32>, pixel: Rgba<f32>) -> Rgba<f32> {
+    let a_s = pixel[3];
+    return Rgba(std::array::from_fn(|i| pixel[i] + cur_pixel[i] * (1f32 - a_s)));
+}
+
+fn sample(texture: &Rgba32FImage, s: f64, t: f64) -> Rgba<f32> {
+    let x = (s * texture.width() as f64).rem_euclid(texture.width() as f64) as u32;
+    let y = (t * texture.height() as f64).rem_euclid(texture.height() as f64) as u32;
+    return *texture.get_pixel(x, y);
+}
+
+fn apply_light(pixel: &mut Rgba<f32>, intensity: f32) {
+    pixel[0] *= intensity;
+    pixel[1] *= intensity;
+    pixel[2] *= intensity;
+}
+
+/// Rasterizes `points`, already in viewport coordinates, at every sample position of `img`. The grid
+/// pattern samples the corners of the supersampled pixels, which is what `rasterize` does anyway.
+/// Other patterns rasterize each sample cell as its own pixel-sized grid shifted onto the cell's
+/// sample, then map the fragments back into the cell.
+fn rasterize_samples<const DIM: usize>(
+    img: &DepthImage,
+    points: &Points<DIM>,
+    rasterize: impl Fn(&Points<DIM>) -> Points<DIM>,
+) -> Points<DIM> {
+    let Sampling { fsaa, pattern, .. } = img.sampling();
+    if pattern == SamplePattern::Grid {
+        return rasterize(points);
+    }
+
+    let n = fsaa as f64;
+    let mut result = Points::<DIM>::default();
+    for j in 0..fsaa {
+        for i in 0..fsaa {
+            let [d_x, d_y] = pattern.offset(fsaa, i, j);
+            let (o_x, o_y) = (i as f64 + d_x, j as f64 + d_y);
 
-    return [r, g, b, a];
+            let mut cell = points.clone();
+            cell.shift_and_scale([-o_x, -o_y], 1f64 / n);
+            let mut fragments = rasterize(&cell);
+            fragments.shift_and_scale([o_x / n, o_y / n], n);
+            result.append(&mut fragments.0);
+        }
+    }
+    return result;
 }
 
+/// The lit vertex color and, if there is a texture, the lit texel at a fragment.
+type Shade = (Rgba<f32>, Option<Rgba<f32>>);
+
 struct Draw<const DIM: usize>();
 
 impl<const DIM: usize> Draw<DIM> {
-    fn draw_points(
-        img: &mut DepthImage,
-        points: Points<DIM>,
-        texture: &Option<Rgba32FImage>,
-        depth: bool,
-        decals: bool,
-    ) {
+    /// Shades `point` on its own, without the pixel it lands on.
+    fn shade(point: &Point<DIM>, state: &RenderState) -> Shade {
+        let mut pixel: Rgba<f32> = point.pixel();
+
+        let intensity = state.light.map(|light| {
+            let normal_texel = state
+                .normal_map
+                .as_ref()
+                .map(|normal_map| sample(normal_map, point[S], point[T]));
+            let visibility = state.shadow_map.as_ref().map_or(1f32, |shadow_map| {
+                shadow_map.visibility(point, state.pcf_radius, state.shadow_bias)
+            });
+            light.intensity(point, normal_texel, visibility)
+        });
+
+        if let Some(intensity) = intensity {
+            apply_light(&mut pixel, intensity);
+        }
+
+        // Textures are converted into the working space when they're loaded
+        let texel = state.texture.as_ref().map(|texture| {
+            let mut temp = sample(texture, point[S], point[T]);
+            if let Some(intensity) = intensity {
+                apply_light(&mut temp, intensity);
+            }
+            temp
+        });
+
+        return (pixel, texel);
+    }
+
+    fn draw_points(img: &mut DepthImage, points: Points<DIM>, state: &RenderState) {
         for point in points {
             if point[X] < 0f64 || point[Y] < 0f64 {
                 continue;
             }
             let (x, y) = (point[X] as u32, point[Y] as u32);
-            if x < img.width() && y < img.height() {
-                let mut pixel: Rgba<f32> = point.pixel();
-                if !depth || point[Z] < img.depth(x, y) {
-                    let cur_pixel = img.get_pixel(x, y).clone();
-
-                    [pixel[0], pixel[1], pixel[2], pixel[3]] = overlay_pixels(cur_pixel, pixel);
-
-                    if let Some(texture) = texture {
-                        let x = (point[S] * texture.width() as f64).rem_euclid(texture.width() as f64) as u32;
-                        let y = (point[T] * texture.height() as f64).rem_euclid(texture.height() as f64) as u32;
-                        let _temp = texture.get_pixel(x, y).clone();
-                        let mut temp: Rgba<f32> = Rgba([0f32; 4]);
-                        let texel = Srgb::from_components((_temp[0], _temp[1], _temp[2])).into_linear::<f32>();
-                        temp[0] = texel.red;
-                        temp[1] = texel.green;
-                        temp[2] = texel.blue;
-                        temp[3] = _temp[3];
-                        if decals {
-                            [pixel[0], pixel[1], pixel[2], pixel[3]] = overlay_pixels(pixel, temp);
-                        } else {
-                            pixel = temp;
-                        }
-                    }
-
-                    img.put_pixel(x, y, pixel, if depth { Some(point[Z]) } else { None });
+            if state.depth_only {
+                if x < img.width() && y < img.height() && point[Z] < img.depth(x, y) {
+                    img.put_depth(x, y, point[Z]);
                 }
+                continue;
+            }
+            if x < img.width() && y < img.height() && (!state.depth || point[Z] < img.depth(x, y)) {
+                Self::put_shade(img, x, y, Self::shade(&point, state), point[Z], state);
+            }
+        }
+    }
+
+    /// Blends a shaded fragment into sample `x`, `y`.
+    fn put_shade(img: &mut DepthImage, x: u32, y: u32, (color, texel): Shade, depth: f64, state: &RenderState) {
+        // The framebuffer holds premultiplied colors, shading gives straight ones
+        let mut pixel = overlay_pixels(img.get_pixel(x, y), premultiply(color));
+
+        if let Some(temp) = texel.map(premultiply) {
+            if state.decals {
+                pixel = overlay_pixels(pixel, temp);
+            } else {
+                pixel = temp;
+            }
+        }
+
+        img.put_pixel(x, y, pixel, if state.depth { Some(depth) } else { None });
+    }
+
+    /// MSAA: `samples` are the primitive's coverage, rasterized with only position and depth, and
+    /// each sample is depth tested on its own. The attributes are interpolated across `plane`, three
+    /// of the primitive's vertices, and shaded once per pixel at the centroid of its covered samples,
+    /// which is always inside the primitive. `varying` are the attributes to undivide by w.
+    fn draw_msaa(
+        img: &mut DepthImage,
+        plane: [Point<DIM>; 3],
+        samples: Points<4>,
+        varying: &[usize],
+        state: &RenderState,
+    ) {
+        let fsaa = img.sampling().fsaa;
+        let samples = samples
+            .into_iter()
+            .filter(|sample| sample[X] >= 0f64 && sample[Y] >= 0f64)
+            .map(|sample| (sample[X] as u32, sample[Y] as u32, sample))
+            .filter(|&(x, y, _)| x < img.width() && y < img.height())
+            .collect::<Vec<(u32, u32, Point<4>)>>();
+        if samples.is_empty() {
+            return;
+        }
+
+        // One entry per pixel the primitive touches: the sum and count of its sample positions,
+        // then its shade once a sample has passed the depth test
+        let (left, top) = samples.iter().fold((u32::MAX, u32::MAX), |(l, t), &(x, y, _)| {
+            (l.min(x / fsaa), t.min(y / fsaa))
+        });
+        let right = samples.iter().map(|&(x, _, _)| x / fsaa).max().unwrap();
+        let bottom = samples.iter().map(|&(_, y, _)| y / fsaa).max().unwrap();
+        let columns = (right - left + 1) as usize;
+        let index = |x: u32, y: u32| (y / fsaa - top) as usize * columns + (x / fsaa - left) as usize;
+
+        let mut centroids = vec![([0f64; 2], 0u32); columns * (bottom - top + 1) as usize];
+        for &(x, y, sample) in &samples {
+            let (sum, count) = &mut centroids[index(x, y)];
+            *sum = [sum[0] + sample[X], sum[1] + sample[Y]];
+            *count += 1;
+        }
+
+        let mut shades: Vec<Option<Shade>> = vec![None; centroids.len()];
+        for (x, y, sample) in samples {
+            if state.depth && sample[Z] >= img.depth(x, y) {
+                continue;
             }
+            let i = index(x, y);
+            let shade = *shades[i].get_or_insert_with(|| {
+                let (sum, count) = centroids[i];
+                let centroid = [sum[0] / count as f64, sum[1] / count as f64];
+                Self::shade(&interpolate(plane, centroid, varying), state)
+            });
+            Self::put_shade(img, x, y, shade, sample[Z], state);
         }
     }
 }
 
-pub(crate) fn draw_triangle(
-    mut img: &mut DepthImage,
-    points: &mut Points<10>,
-    uniform_matrix: &Array2<f64>,
-    texture: &Option<Rgba32FImage>,
-    depth: bool,
-    hyp: bool,
-    cull: bool,
-    decals: bool,
-) {
-    points.multiply_by_matrix(&uniform_matrix);
-
-    if cull && points.is_back_face() {
+/// The attributes at `position` in the plane through `plane`'s vertices, which is how the
+/// rasterizer interpolates them too.
+fn interpolate<const DIM: usize>(plane: [Point<DIM>; 3], position: [f64; 2], varying: &[usize]) -> Point<DIM> {
+    let [a, b, c] = plane;
+    let edge =
+        |p: Point<DIM>, q: Point<DIM>| (q[X] - p[X]) * (position[1] - p[Y]) - (q[Y] - p[Y]) * (position[0] - p[X]);
+    let area = (b[X] - a[X]) * (c[Y] - a[Y]) - (b[Y] - a[Y]) * (c[X] - a[X]);
+    if area == 0f64 {
+        return a;
+    }
+
+    let (w_a, w_b) = (edge(b, c) / area, edge(c, a) / area);
+    let mut point = w_a * a + w_b * b + (1f64 - w_a - w_b) * c;
+    if !varying.is_empty() {
+        point.undivide_by_w(varying);
+    }
+    return point;
+}
+
+/// The position and depth of `points`, all MSAA needs to rasterize.
+fn coverage<const DIM: usize>(points: &[Point<DIM>]) -> Points<4> {
+    return Points(points.iter().map(|p| Point::new([p[X], p[Y], p[Z], p[W]])).collect());
+}
+
+pub(crate) fn draw_triangle(img: &mut DepthImage, points: &mut Points<21>, state: &RenderState) {
+    if state.light.is_some() && !state.depth_only {
+        fill_tangent_frame(points);
+        transform_tangent_frame(points, &state.model_matrix);
+
+        if let Some(shadow_map) = &state.shadow_map {
+            points.project_to_light(&(shadow_map.matrix * state.model_matrix));
+        }
+    }
+
+    points.multiply_by_matrix(&state.transform());
+
+    if state.cull && points.is_back_face() {
         return;
     }
 
-    if hyp {
-        points.divide_by_w(&Box::from([X, Y, Z, R, G, B, A, S, T]));
+    if state.hyp {
+        points.divide_by_w(&[X, Y, Z, R, G, B, A, S, T, NX, NY, NZ, TX, TY, TZ, TW, LX, LY, LZ, LW]);
     } else {
-        points.divide_by_w(&Box::from([X, Y]));
+        points.divide_by_w(&[X, Y]);
     }
 
     points.transform_to_viewport(img.width(), img.height());
 
-    let mut triangle = triangle(points[0], points[1], points[2]);
+    if img.sampling().msaa && !state.depth_only {
+        let mut samples = rasterize_samples(img, &coverage(&points.0), |p| triangle(p[0], p[1], p[2]));
+        let varying: &[usize] = if state.hyp { &VARYING } else { &[] };
+        if state.hyp {
+            samples.undivide_by_w(&[Z]);
+        }
+        Draw::<21>::draw_msaa(img, [points[0], points[1], points[2]], samples, varying, state);
+        return;
+    }
+
+    let mut triangle = rasterize_samples(img, points, |points| triangle(points[0], points[1], points[2]));
 
-    if hyp {
-        triangle.undivide_by_w(&Box::from([Z, R, G, B, A, S, T]));
+    if state.hyp {
+        triangle.undivide_by_w(&VARYING);
     }
 
-    Draw::<10>::draw_points(&mut img, triangle, texture, depth, decals);
+    Draw::<21>::draw_points(img, triangle, state);
 }
 
-pub(crate) fn draw_point(
-    mut img: &mut DepthImage,
-    point: &mut Point<11>,
-    uniform_matrix: &Array2<f64>,
-    texture: &Option<Rgba32FImage>,
-    depth: bool,
-    decals: bool,
-) {
-    point.multiply_by_matrix(&uniform_matrix);
+pub(crate) fn draw_point(img: &mut DepthImage, point: &mut Point<22>, state: &RenderState) {
+    point.multiply_by_matrix(&state.transform());
 
-    point.divide_by_w(&Box::from([X, Y]));
+    point.divide_by_w(&[X, Y]);
 
     point.transform_to_viewport(img.width(), img.height());
 
-    let square: Points<11> = square(*point);
+    if img.sampling().msaa && !state.depth_only {
+        let corners = square_corners(*point);
+        let samples = rasterize_samples(img, &coverage(&corners), |p| quad([p[0], p[1], p[2], p[3]]));
+        // The texture coordinates are the only attributes that vary, and they're affine across the
+        // whole square
+        Draw::<22>::draw_msaa(img, [corners[0], corners[1], corners[2]], samples, &[], state);
+        return;
+    }
+
+    let square: Points<22> = rasterize_samples(img, &Points(vec![*point]), |points| square(points[0]));
 
-    Draw::<11>::draw_points(&mut img, square, texture, depth, decals);
+    Draw::<22>::draw_points(img, square, state);
 }
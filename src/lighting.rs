@@ -0,0 +1,121 @@
+use image::Rgba;
+
+use crate::axis::axis::{NX, S, T, TW, TX, X};
+use crate::math::{add, cross, dot, normalize, scale, sub, Mat4, Vec3};
+use crate::point::{Point, Points};
+
+// Gram-Schmidt: removes the component of `a` along the unit vector `n`
+fn orthogonalize(a: Vec3, n: Vec3) -> Vec3 {
+    return sub(a, scale(n, dot(n, a)));
+}
+
+#[derive(Copy, Clone)]
+pub(crate) struct Light {
+    direction: Vec3,
+    ambient: f64,
+}
+
+impl Light {
+    pub(crate) fn new(light: Vec<f64>) -> Self {
+        let ambient = if light.len() > 3 { light[3] } else { 0f64 };
+        Self {
+            direction: normalize([light[0], light[1], light[2]]),
+            ambient,
+        }
+    }
+
+    /// Lambertian intensity at `point`, using the interpolated normal perturbed by `normal_texel` (a
+    /// tangent-space normal map sample) if there is one. `visibility` is the unshadowed fraction of
+    /// the point, which scales the diffuse term but not the ambient one.
+    pub(crate) fn intensity<const DIM: usize>(
+        &self,
+        point: &Point<DIM>,
+        normal_texel: Option<Rgba<f32>>,
+        visibility: f32,
+    ) -> f32 {
+        let n = normalize(point.vec3(NX));
+        if n == [0f64; 3] {
+            // Nothing to light, e.g. points, which carry no normals
+            return 1f32;
+        }
+
+        let normal = if let Some(texel) = normal_texel {
+            let t = normalize(orthogonalize(point.vec3(TX), n));
+            let b = scale(cross(n, t), point[TW].signum());
+            let [m_x, m_y, m_z] = [texel[0], texel[1], texel[2]].map(|c| 2f64 * c as f64 - 1f64);
+            normalize(add(add(scale(t, m_x), scale(b, m_y)), scale(n, m_z)))
+        } else {
+            n
+        };
+
+        let diffuse = dot(normal, self.direction).max(0f64) * visibility as f64;
+        return (self.ambient + (1f64 - self.ambient) * diffuse) as f32;
+    }
+}
+
+/// Fills in missing per-vertex normals with the face normal, and missing tangents with the one
+/// implied by the triangle's positions and texcoords.
+pub(crate) fn fill_tangent_frame(points: &mut Points<21>) {
+    assert_eq!(points.len(), 3);
+
+    let e1 = sub(points[1].vec3(X), points[0].vec3(X));
+    let e2 = sub(points[2].vec3(X), points[0].vec3(X));
+    let face_normal = normalize(cross(e1, e2));
+
+    let (du1, dv1) = (points[1][S] - points[0][S], points[1][T] - points[0][T]);
+    let (du2, dv2) = (points[2][S] - points[0][S], points[2][T] - points[0][T]);
+    let det = du1 * dv2 - du2 * dv1;
+
+    // Degenerate texcoords have no meaningful tangent, so fall back to any frame around the normal
+    let (tangent, bitangent) = if det != 0f64 {
+        (
+            scale(sub(scale(e1, dv2), scale(e2, dv1)), 1f64 / det),
+            scale(sub(scale(e2, du1), scale(e1, du2)), 1f64 / det),
+        )
+    } else {
+        (e1, cross(face_normal, e1))
+    };
+
+    for point in &mut points.0 {
+        if point.vec3(NX) == [0f64; 3] {
+            point.set_vec3(NX, face_normal);
+        }
+
+        if point.vec3(TX) == [0f64; 3] && point[TW] == 0f64 {
+            let n = normalize(point.vec3(NX));
+            let t = normalize(orthogonalize(tangent, n));
+            point.set_vec3(TX, t);
+            point[TW] = if dot(cross(n, t), bitangent) < 0f64 {
+                -1f64
+            } else {
+                1f64
+            };
+        }
+    }
+}
+
+fn mul3(matrix: &[Vec3; 3], a: Vec3) -> Vec3 {
+    return matrix.map(|row| dot(row, a));
+}
+
+/// Moves normals and tangents into world space with the model matrix, so they can be lit by a light
+/// that doesn't move with the model. Normals use the cofactor matrix, which is the inverse
+/// transpose up to a positive scale factor that normalizing removes anyway.
+pub(crate) fn transform_tangent_frame(points: &mut Points<21>, model_matrix: &Mat4) {
+    let m: [Vec3; 3] = model_matrix.linear_rows();
+    let columns: [Vec3; 3] = [0, 1, 2].map(|j| [m[0][j], m[1][j], m[2][j]]);
+    // Rows of the adjugate, i.e. det(m) * m^-1
+    let adjugate: [Vec3; 3] = [
+        cross(columns[1], columns[2]),
+        cross(columns[2], columns[0]),
+        cross(columns[0], columns[1]),
+    ];
+    // Keep the sign of det(m) so mirroring transforms don't flip normals inside out
+    let sign = dot(columns[0], adjugate[0]).signum();
+    let cofactor: [Vec3; 3] = [0, 1, 2].map(|i| scale([adjugate[0][i], adjugate[1][i], adjugate[2][i]], sign));
+
+    for point in &mut points.0 {
+        point.set_vec3(NX, mul3(&cofactor, point.vec3(NX)));
+        point.set_vec3(TX, mul3(&m, point.vec3(TX)));
+    }
+}
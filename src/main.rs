@@ -1,34 +1,49 @@
-use std::fs::File;
-use std::io::BufRead;
-use std::path::Path;
+use std::collections::HashMap;
+use std::env;
+use std::process::ExitCode;
 use std::slice::Iter;
 use std::str::FromStr;
-use std::{env, io};
+use std::time::Instant;
 
 use image::{io::Reader as ImageReader, Rgba, Rgba32FImage};
-use ndarray::Array2;
 
+use crate::cli::{Command, Log, RenderArgs};
 use crate::color::Color;
-use crate::depth_image::DepthImage;
+use crate::color_space::{convert_image, ColorSpace, ColorSpaces};
+use crate::depth_image::{DepthImage, DepthResolve, OutputFormat, OutputOptions};
 use crate::draw::{draw_point, draw_triangle};
+use crate::lighting::Light;
+use crate::math::{Mat4, Quat};
 use crate::point::{Point, Points};
 use crate::position::Position;
+use crate::quantize::Dither;
+use crate::render_state::RenderState;
+use crate::sampling::{ResolveFilter, SamplePattern, Sampling};
+use crate::script::{Location, Script};
+use crate::shadow::ShadowPass;
+use crate::tonemap::{ToneMap, ToneMapOperator};
 
 mod axis;
+mod cli;
 mod color;
+mod color_profile;
+mod color_space;
+mod compare;
 mod depth_image;
 mod draw;
+mod lighting;
+mod math;
+mod matrix;
+mod obj;
 mod point;
 mod position;
+mod quantize;
 mod rasterize;
-
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
-}
+mod render_state;
+mod sampling;
+mod script;
+mod shadow;
+mod tonemap;
 
 fn read_args<P>(fields: Iter<&str>) -> Result<Vec<P>, <P as FromStr>::Err>
 where
@@ -48,319 +63,1136 @@ where
     return Ok(result);
 }
 
-fn main() {
-    if env::args().len() < 2 {
-        return;
+/// The image draws go to: the named framebuffer if one is bound, otherwise the output image.
+fn target<'a>(
+    img: &'a mut DepthImage,
+    framebuffers: &'a mut HashMap<String, DepthImage>,
+    bound: &Option<String>,
+    shadow_pass: &'a mut Option<ShadowPass>,
+) -> &'a mut DepthImage {
+    if let Some(shadow_pass) = shadow_pass {
+        return &mut shadow_pass.depth;
+    }
+    if let Some(framebuffer) = bound.as_ref().and_then(|name| framebuffers.get_mut(name)) {
+        return framebuffer;
+    }
+    return img;
+}
+
+/// Whether draws go to the output image, which is only worth saving when they do.
+fn draws_to_output(
+    framebuffers: &HashMap<String, DepthImage>,
+    bound: &Option<String>,
+    shadow_pass: &Option<ShadowPass>,
+) -> bool {
+    return shadow_pass.is_none() && !bound.as_ref().is_some_and(|name| framebuffers.contains_key(name));
+}
+
+fn read_image(filename: &str) -> Result<Rgba32FImage, &'static str> {
+    if let Ok(file) = ImageReader::open(filename) {
+        if let Ok(image) = file.decode() {
+            return Ok(image.into_rgba32f());
+        }
+        return Err("Unable to decode texture");
     }
+    return Err("Unable to open file");
+}
+
+/// Writes the output image, reporting where it went or why it couldn't.
+fn save(
+    img: &mut DepthImage,
+    filename: &str,
+    format: OutputFormat,
+    colors: ColorSpaces,
+    options: OutputOptions,
+    log: &mut Log,
+) {
+    match img.save(String::from(filename), format, colors, options) {
+        Ok(()) => log.info(format!("Wrote {}", filename)),
+        Err(err) => log.io_error(err),
+    }
+}
 
-    let in_filename = env::args().nth(1).unwrap();
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    return match cli::parse(&args) {
+        Ok(Command::Render(render_args)) => render(&render_args),
+        Ok(Command::Compare { a, b, heatmap }) => compare::run(&a, &b, heatmap.as_deref()),
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}.\n\n{}", err, cli::USAGE);
+            ExitCode::from(cli::USAGE_ERROR)
+        }
+    };
+}
+
+/// Runs a script. Options given on the command line take precedence over the script's own.
+fn render(cli: &RenderArgs) -> ExitCode {
+    let mut log = Log::new(cli.verbosity);
+    let start = Instant::now();
 
     let mut out_filename: String = String::default();
+    let mut out_format: OutputFormat = OutputFormat::Png;
+    // Whether the image has changed since it was last written, and whether to write it after every draw
+    let mut unsaved: bool = false;
+    let mut autosave: bool = false;
     let mut img: DepthImage = DepthImage::default();
 
-    let mut texture: Option<Rgba32FImage> = None;
+    let mut state: RenderState = RenderState::default();
+
+    // Offscreen render targets, and which of them draws go to instead of the output image
+    let mut framebuffers: HashMap<String, DepthImage> = HashMap::new();
+    let mut bound: Option<String> = None;
+    let mut shadow_pass: Option<ShadowPass> = None;
 
     let mut position_buf: Vec<Position> = vec![];
     let mut color_buf: Vec<Color> = vec![];
     let mut texcoord_buf: Vec<[f64; 2]> = vec![];
+    let mut normal_buf: Vec<[f64; 3]> = vec![];
+    let mut tangent_buf: Vec<[f64; 4]> = vec![];
     let mut pointsize_buf: Vec<f64> = vec![];
     let mut element_buf: Vec<usize> = vec![];
 
-    let mut depth: bool = false;
-    let mut s_rgb: bool = false;
-    let mut hyp: bool = false;
-    let mut fsaa: u32 = 1;
-    let mut cull: bool = false;
-    let mut decals: bool = false;
+    let mut colors: ColorSpaces = ColorSpaces::default();
+    let mut options: OutputOptions = OutputOptions::default();
+    let mut sampling: Sampling = Sampling::default();
+    if cli.srgb {
+        colors.output = ColorSpace::Srgb;
+    }
+    if let Some(fsaa) = cli.fsaa {
+        sampling.fsaa = fsaa;
+    }
+    let mut variables = cli.defines.clone();
     // let mut frustum: bool = false;
 
-    let mut uniform_matrix: Array2<f64> = Array2::eye(4);
-
-    let mut line_no = 0;
+    let mut location = Location::default();
     let mut invalid = false;
     let mut err = "Unknown";
 
-    if let Ok(lines) = read_lines(in_filename) {
-        for _line in lines {
+    if let Ok(mut script) = Script::open(&cli.script) {
+        while let Some((_location, _line)) = script.next_line() {
             if invalid {
-                eprintln!("Error on line {}: {}.", line_no, err);
+                log.line_error(&location, err);
                 invalid = false;
                 err = "Unknown";
             }
-            line_no += 1;
+            location = _location;
 
-            if let Ok(line) = _line {
-                let trim_line = line.trim();
-                let fields = trim_line.split_whitespace().collect::<Vec<&str>>();
-                if fields.len() == 0 {
+            // Comments are dropped before expanding, so a `$` in one needn't name a variable
+            let line = match _line.and_then(|line| script::expand(line.split('#').next().unwrap(), &variables)) {
+                Ok(line) => line,
+                Err(_err) => {
+                    invalid = true;
+                    err = _err;
                     continue;
                 }
+            };
+            let trim_line = line.trim();
+            let fields = trim_line.split_whitespace().collect::<Vec<&str>>();
+            if fields.is_empty() {
+                continue;
+            }
+
+            match fields[0] {
+                "include" => {
+                    if let Some(&path) = fields.get(1) {
+                        if let Err(_err) = script.include(path) {
+                            invalid = true;
+                            err = _err;
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Expected a file name";
+                    }
+                }
+                "repeat" => {
+                    if let Some(Ok(count)) = fields.get(1).map(|count| count.parse::<u64>()) {
+                        if let Err(_err) = script.repeat(count) {
+                            invalid = true;
+                            err = _err;
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Expected a count";
+                    }
+                }
+                "end" => {
+                    if let Err(_err) = script.end() {
+                        invalid = true;
+                        err = _err;
+                    }
+                }
+                "set" => {
+                    if fields.len() < 3 || !script::is_variable_name(fields[1]) {
+                        invalid = true;
+                        err = "Expected a name and a value";
+                        continue;
+                    }
+
+                    // Values can be several fields, and defines from the command line win
+                    if !cli.defines.contains_key(fields[1]) {
+                        variables.insert(String::from(fields[1]), fields[2..].join(" "));
+                    }
+                }
+                "png" | "png16" | "exr" | "output" => {
+                    // `output` names its encoder explicitly, the others are shorthands for it
+                    let (format, args) = if fields[0] == "output" {
+                        let format = fields.get(1).and_then(|&name| OutputFormat::from_name(name));
+                        (format, fields.get(2..).unwrap_or_default())
+                    } else {
+                        let format = fields
+                            .get(3)
+                            .and_then(|&path| OutputFormat::from_command(fields[0], path));
+                        (format, &fields[1..])
+                    };
+                    if format.is_none() || args.len() < 3 {
+                        invalid = true;
+                        err = "Expected a format, dimensions and a file name";
+                        continue;
+                    }
+
+                    let mut dim: Vec<u32> = vec![];
+                    for field in args[0..=1].iter() {
+                        let parsed = field.parse::<u32>().ok();
+                        invalid = parsed.is_none();
 
-                match fields[0] {
-                    "png" => {
-                        let mut dim: Vec<u32> = vec![];
-                        for field in fields[1..=2].iter() {
-                            let parsed = field.parse::<u32>().ok();
-                            invalid = parsed == None;
+                        if !invalid {
+                            dim.push(parsed.unwrap());
+                        }
+                    }
+                    if invalid || dim.len() < 2 {
+                        err = "Invalid dimensions";
+                        continue;
+                    }
 
-                            if !invalid {
-                                dim.push(parsed.unwrap());
+                    let width = cli.width.unwrap_or(dim[0]);
+                    let height = cli.height.unwrap_or(dim[1]);
+                    match DepthImage::from_pixel(width, height, Rgba([0f32; 4]), sampling) {
+                        Ok(image) => {
+                            // Finish the previous image before starting on the next
+                            if unsaved && !out_filename.is_empty() {
+                                save(&mut img, &out_filename, out_format, colors, options, &mut log);
                             }
+                            img = image;
                         }
-                        if invalid || dim.len() < 2 {
-                            err = "Invalid dimensions";
+                        Err(_err) => {
+                            invalid = true;
+                            err = _err;
                             continue;
                         }
+                    }
+
+                    out_format = format.unwrap();
+                    out_filename = script.resolve(args[2], cli.output_dir.as_deref());
+                    if let Some(output) = &cli.output {
+                        out_format = out_format.with_extension_of(output);
+                        out_filename = output.clone();
+                    }
+                    // Nothing needs writing until something is drawn into it
+                    unsaved = false;
+                }
+                "save" | "flush" => {
+                    if out_filename.is_empty() {
+                        invalid = true;
+                        err = "No output image";
+                        continue;
+                    }
 
-                        img = DepthImage::from_pixel(dim[0], dim[1], Rgba([0f32; 4]), fsaa);
+                    save(&mut img, &out_filename, out_format, colors, options, &mut log);
+                    unsaved = false;
+                }
+                "autosave" => {
+                    autosave = true;
+                }
+                "saveDepth" => {
+                    if fields.len() < 2 {
+                        invalid = true;
+                        err = "Expected a file name";
+                        continue;
+                    }
 
-                        out_filename = String::from(fields[3]);
-                        if let Err(err) = img.save(out_filename.clone(), s_rgb) {
-                            eprintln!("{}", err);
+                    let resolve = fields
+                        .get(2)
+                        .map_or(Some(DepthResolve::Min), |&name| DepthResolve::from_name(name));
+                    let far = fields.get(3).map_or(Ok(1f64), |&far| far.parse::<f64>());
+                    if let (Some(resolve), Ok(far)) = (resolve, far) {
+                        let path = script.resolve(fields[1], cli.output_dir.as_deref());
+                        match img.save_depth(&path, resolve, far) {
+                            Ok(()) => log.info(format!("Wrote {}", path)),
+                            Err(err) => log.io_error(err),
                         }
+                    } else {
+                        invalid = true;
+                        err = "Expected min, max or average and a far value";
                     }
-                    "depth" => {
-                        depth = true;
+                }
+                "depth" => {
+                    state.depth = true;
+                }
+                "s_rgb" | "sRGB" => {
+                    colors.output = ColorSpace::Srgb;
+                }
+                "colorSpace" => {
+                    let space = fields.get(2).and_then(|&name| ColorSpace::from_name(name));
+                    match (fields.get(1), space) {
+                        (Some(&"working"), Some(space)) if space.is_linear() => colors.working = space,
+                        (Some(&"working"), Some(_)) => {
+                            invalid = true;
+                            err = "The working space must be linear";
+                        }
+                        (Some(&"texture"), Some(space)) => colors.texture = space,
+                        (Some(&"vertex"), Some(space)) => colors.vertex = space,
+                        (Some(&"output"), Some(space)) => {
+                            if !cli.srgb {
+                                colors.output = space;
+                            }
+                        }
+                        _ => {
+                            invalid = true;
+                            err = "Expected working, texture, vertex or output and a color space";
+                        }
                     }
-                    "s_rgb" | "sRGB" => {
-                        s_rgb = true;
+                }
+                "tonemap" => {
+                    let operator = fields.get(1).and_then(|&name| ToneMapOperator::from_name(name));
+                    let exposure = fields.get(2).map_or(Ok(0f32), |&exposure| exposure.parse::<f32>());
+                    if let (Some(operator), Ok(exposure)) = (operator, exposure) {
+                        options.tone_map = ToneMap { operator, exposure };
+                    } else {
+                        invalid = true;
+                        err = "Expected none, reinhard or aces and an optional exposure";
                     }
-                    "hyp" => {
-                        hyp = true;
+                }
+                "dither" => {
+                    if let Some(_dither) = fields.get(1).and_then(|&name| Dither::from_name(name)) {
+                        options.dither = _dither;
+                    } else {
+                        invalid = true;
+                        err = "Expected none, ordered or bluenoise";
                     }
-                    "fsaa" => {
-                        if let Ok(_fsaa) = fields[1].parse::<u32>() {
-                            if !(1..=8).contains(&_fsaa) {
-                                invalid = true;
-                                err = "Value must be within the range [1, 8]";
-                                continue;
-                            }
+                }
+                "outputAlpha" => match fields.get(1) {
+                    Some(&"straight") => options.premultiplied = false,
+                    Some(&"premultiplied") => options.premultiplied = true,
+                    _ => {
+                        invalid = true;
+                        err = "Expected straight or premultiplied";
+                    }
+                },
+                "hyp" => {
+                    state.hyp = true;
+                }
+                "fsaa" | "msaa" => {
+                    let level = fields.get(1).and_then(|level| level.parse::<u32>().ok());
+                    if let Some(_fsaa) = level.map(|fsaa| cli.fsaa.unwrap_or(fsaa)) {
+                        if !(1..=8).contains(&_fsaa) {
+                            invalid = true;
+                            err = "Value must be within the range [1, 8]";
+                            continue;
+                        }
 
-                            fsaa = _fsaa;
-                            img = DepthImage::from_pixel(img.width(), img.height(), Rgba([0f32; 4]), fsaa);
-                        } else {
+                        let mut _sampling = sampling;
+                        _sampling.fsaa = _fsaa;
+                        _sampling.msaa = fields[0] == "msaa";
+                        // Whatever has been drawn is kept, so it doesn't matter whether this
+                        // comes before or after the output command
+                        if let Err(_err) = img.resample(_sampling) {
                             invalid = true;
-                            err = "Value must be an integer";
+                            err = _err;
+                            continue;
                         }
+                        sampling = _sampling;
+                    } else {
+                        invalid = true;
+                        err = "Value must be an integer";
                     }
-                    "cull" => {
-                        cull = true;
+                }
+                "fsaaPattern" => {
+                    if let Some(pattern) = fields.get(1).and_then(|&name| SamplePattern::from_name(name)) {
+                        sampling.pattern = pattern;
+                        img.set_pattern(pattern);
+                    } else {
+                        invalid = true;
+                        err = "Expected grid, centered, rotated or jittered";
                     }
-                    "decals" => {
-                        decals = true;
+                }
+                "fsaaFilter" => {
+                    if let Some(filter) = fields.get(1).and_then(|&name| ResolveFilter::from_name(name)) {
+                        sampling.filter = filter;
+                        img.set_filter(filter);
+                    } else {
+                        invalid = true;
+                        err = "Expected box, tent, gaussian or lanczos";
                     }
-                    "frustum" => {}
-                    "texture" => {
-                        let texture_filename = String::from(fields[1]);
-                        if let Ok(file) = ImageReader::open(texture_filename) {
-                            if let Ok(image) = file.decode() {
-                                texture = Some(image.into_rgba32f());
-                            } else {
-                                invalid = true;
-                                err = "Unable to decode texture";
+                }
+                "cull" => {
+                    state.cull = true;
+                }
+                "decals" => {
+                    state.decals = true;
+                }
+                "frustum" => {}
+                "framebuffer" => match (fields.get(1), fields.get(2)) {
+                    (Some(&"create"), Some(&name)) => {
+                        let dim = read_args::<u32>(fields[3..].iter());
+                        if let Ok([width, height]) = dim.as_deref() {
+                            match DepthImage::from_pixel(*width, *height, Rgba([0f32; 4]), sampling) {
+                                Ok(framebuffer) => {
+                                    framebuffers.insert(String::from(name), framebuffer);
+                                }
+                                Err(_err) => {
+                                    invalid = true;
+                                    err = _err;
+                                }
                             }
                         } else {
                             invalid = true;
-                            err = "Unable to open file";
+                            err = "Invalid dimensions";
                         }
                     }
-                    "uniformMatrix" => {
-                        if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
-                            for j in 0..16 {
-                                uniform_matrix[[j % 4, j / 4]] = args[j];
-                            }
+                    (Some(&"texture"), Some(&name)) => {
+                        if let Some(framebuffer) = framebuffers.get_mut(name) {
+                            // A snapshot: drawing into the framebuffer later doesn't change the
+                            // texture until it is bound again
+                            state.texture = Some(framebuffer.resolve(colors.working));
+                        } else {
+                            invalid = true;
+                            err = "No such framebuffer";
                         }
                     }
-                    "position" => {
-                        if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
-                            let size = args[0] as usize;
-                            if !(1..=4).contains(&size) {
-                                invalid = true;
-                                err = "Invalid size";
-                                continue;
-                            }
+                    _ => {
+                        invalid = true;
+                        err = "Expected create or texture and a name";
+                    }
+                },
+                "bind" => match fields.get(1) {
+                    Some(&"default") => bound = None,
+                    Some(&name) if framebuffers.contains_key(name) => bound = Some(String::from(name)),
+                    _ => {
+                        invalid = true;
+                        err = "No such framebuffer";
+                    }
+                },
+                "texture" => {
+                    let Some(&path) = fields.get(1) else {
+                        invalid = true;
+                        err = "Expected a file name";
+                        continue;
+                    };
+                    match read_image(&script.resolve(path, cli.asset_dir.as_deref())) {
+                        Ok(mut image) => {
+                            convert_image(&mut image, colors.texture, colors.working);
+                            state.texture = Some(image);
+                        }
+                        Err(_err) => {
+                            invalid = true;
+                            err = _err;
+                        }
+                    }
+                }
+                "normalmap" => {
+                    let Some(&path) = fields.get(1) else {
+                        invalid = true;
+                        err = "Expected a file name";
+                        continue;
+                    };
+                    match read_image(&script.resolve(path, cli.asset_dir.as_deref())) {
+                        Ok(image) => state.normal_map = Some(image),
+                        Err(_err) => {
+                            invalid = true;
+                            err = _err;
+                        }
+                    }
+                }
+                "light" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if !(3..=4).contains(&args.len()) {
+                            invalid = true;
+                            err = "Expected a direction and optional ambient term";
+                            continue;
+                        }
 
-                            position_buf.clear();
-                            for j in (1..=args.len() - size).step_by(size) {
-                                let position = args[j..j + size].to_vec();
-                                position_buf.push(Position::new(position));
+                        state.light = Some(Light::new(args));
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "shadowmap" => match fields.get(1) {
+                    Some(&"begin") => {
+                        if shadow_pass.is_some() {
+                            invalid = true;
+                            err = "Shadow map pass already begun";
+                            continue;
+                        }
+
+                        let dim = read_args::<u32>(fields[2..].iter());
+                        if let Ok([width, height]) = dim.as_deref() {
+                            match ShadowPass::begin(*width, *height, &mut state) {
+                                Ok(pass) => shadow_pass = Some(pass),
+                                Err(_err) => {
+                                    invalid = true;
+                                    err = _err;
+                                }
                             }
                         } else {
                             invalid = true;
-                            err = "Invalid values";
+                            err = "Invalid dimensions";
+                        }
+                    }
+                    Some(&"end") => {
+                        if let Some(pass) = shadow_pass.take() {
+                            state.shadow_map = Some(pass.end(&mut state));
+                        } else {
+                            invalid = true;
+                            err = "No shadow map pass to end";
                         }
                     }
-                    "color" => {
-                        if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
-                            let size = args[0] as usize;
-                            if !(3..=4).contains(&size) {
+                    Some(&"off") => state.shadow_map = None,
+                    _ => {
+                        invalid = true;
+                        err = "Expected begin, end or off";
+                    }
+                },
+                "pcf" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if !(1..=2).contains(&args.len()) || args[0] < 0f64 {
+                            invalid = true;
+                            err = "Expected a radius and optional bias";
+                            continue;
+                        }
+
+                        state.pcf_radius = args[0] as u32;
+                        if let Some(&bias) = args.get(1) {
+                            state.shadow_bias = bias;
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "uniformMatrix" | "modelMatrix" | "viewMatrix" | "projectionMatrix" | "multMatrix" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        match matrix::from_column_major(&args) {
+                            Ok(matrix) => match fields[0] {
+                                "uniformMatrix" => state.uniform_matrix = matrix,
+                                "modelMatrix" => state.model_matrix = matrix,
+                                "viewMatrix" => state.view_matrix = matrix,
+                                "projectionMatrix" => state.projection_matrix = matrix,
+                                _ => state.mult_matrix(&matrix),
+                            },
+                            Err(_err) => {
                                 invalid = true;
-                                err = "Invalid size";
-                                continue;
+                                err = _err;
                             }
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "rotateEuler" => {
+                    if fields.len() != 5 {
+                        invalid = true;
+                        err = "Expected a rotation order and 3 angles";
+                        continue;
+                    }
 
-                            color_buf.clear();
-                            for j in (1..=args.len() - size).step_by(size) {
-                                let color = args[j..j + size].to_vec();
-                                color_buf.push(Color::new(color));
+                    if let Ok(args) = read_args::<f64>(fields[2..].iter()) {
+                        match Quat::from_euler(fields[1], [args[0], args[1], args[2]]) {
+                            Ok(rotation) => state.mult_matrix(&rotation.to_mat4()),
+                            Err(_err) => {
+                                invalid = true;
+                                err = _err;
                             }
-                        } else {
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "rotateQuat" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 4 {
+                            invalid = true;
+                            err = "Expected w, x, y and z";
+                            continue;
+                        }
+
+                        let rotation = Quat {
+                            w: args[0],
+                            x: args[1],
+                            y: args[2],
+                            z: args[3],
+                        };
+                        state.mult_matrix(&rotation.to_mat4());
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "rotateSlerp" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 9 {
                             invalid = true;
-                            err = "Invalid values";
+                            err = "Expected t and two quaternions";
+                            continue;
                         }
+
+                        let from = Quat {
+                            w: args[1],
+                            x: args[2],
+                            y: args[3],
+                            z: args[4],
+                        };
+                        let to = Quat {
+                            w: args[5],
+                            x: args[6],
+                            y: args[7],
+                            z: args[8],
+                        };
+                        state.mult_matrix(&from.slerp(to, args[0]).to_mat4());
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
                     }
-                    "texcoord" => {
-                        if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
-                            let size = args[0] as usize;
-                            if size != 2 {
+                }
+                "lookAt" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 9 {
+                            invalid = true;
+                            err = "Expected eye, center and up vectors";
+                            continue;
+                        }
+
+                        let eye = [args[0], args[1], args[2]];
+                        let center = [args[3], args[4], args[5]];
+                        let up = [args[6], args[7], args[8]];
+                        match matrix::look_at(eye, center, up) {
+                            Ok(matrix) => state.view_matrix = matrix,
+                            Err(_err) => {
                                 invalid = true;
-                                err = "Invalid size";
-                                continue;
+                                err = _err;
                             }
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "perspective" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 4 {
+                            invalid = true;
+                            err = "Expected fovy, aspect, near and far";
+                            continue;
+                        }
 
-                            texcoord_buf.clear();
-                            for j in (1..=args.len() - 2).step_by(2) {
-                                let texcoord: [f64; 2] = [args[j], args[j + 1]];
-                                texcoord_buf.push(texcoord);
+                        match matrix::perspective(args[0], args[1], args[2], args[3]) {
+                            Ok(matrix) => state.projection_matrix = matrix,
+                            Err(_err) => {
+                                invalid = true;
+                                err = _err;
                             }
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "ortho" | "frustumMatrix" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 6 {
+                            invalid = true;
+                            err = "Expected left, right, bottom, top, near and far";
+                            continue;
+                        }
+
+                        let result = if fields[0] == "ortho" {
+                            matrix::ortho(args[0], args[1], args[2], args[3], args[4], args[5])
                         } else {
+                            matrix::frustum(args[0], args[1], args[2], args[3], args[4], args[5])
+                        };
+                        match result {
+                            Ok(matrix) => state.projection_matrix = matrix,
+                            Err(_err) => {
+                                invalid = true;
+                                err = _err;
+                            }
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "loadIdentity" => {
+                    state.model_matrix = Mat4::identity();
+                }
+                "push" => {
+                    state.push_matrix();
+                }
+                "pop" => {
+                    if let Err(_err) = state.pop_matrix() {
+                        invalid = true;
+                        err = _err;
+                    }
+                }
+                "translate" | "scale" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 3 {
                             invalid = true;
-                            err = "Invalid values";
+                            err = "Expected 3 values";
+                            continue;
                         }
+
+                        if fields[0] == "translate" {
+                            state.mult_matrix(&matrix::translation(args[0], args[1], args[2]));
+                        } else {
+                            state.mult_matrix(&matrix::scaling(args[0], args[1], args[2]));
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
                     }
-                    "pointsize" => {
-                        if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
-                            let size = args[0] as usize;
-                            if size != 1 {
+                }
+                "rotate" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.len() != 4 {
+                            invalid = true;
+                            err = "Expected an angle and an axis";
+                            continue;
+                        }
+
+                        match matrix::rotation(args[0], args[1], args[2], args[3]) {
+                            Ok(matrix) => state.mult_matrix(&matrix),
+                            Err(_err) => {
                                 invalid = true;
-                                err = "Invalid size";
-                                continue;
+                                err = _err;
                             }
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "position" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.is_empty() {
+                            invalid = true;
+                            err = "Expected a size and values";
+                            continue;
+                        }
 
-                            pointsize_buf = args[1..].to_vec();
-                        } else {
+                        let size = args[0] as usize;
+                        if !(1..=4).contains(&size) {
                             invalid = true;
-                            err = "Invalid values";
+                            err = "Invalid size";
+                            continue;
                         }
+
+                        position_buf.clear();
+                        for j in (1..=args.len().saturating_sub(size)).step_by(size) {
+                            let position = args[j..j + size].to_vec();
+                            position_buf.push(Position::new(position));
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
                     }
-                    "elements" => {
-                        if let Ok(elements) = read_args::<usize>(fields[1..].iter()) {
-                            element_buf = elements;
-                        } else {
+                }
+                "color" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.is_empty() {
+                            invalid = true;
+                            err = "Expected a size and values";
+                            continue;
+                        }
+
+                        let size = args[0] as usize;
+                        if !(3..=4).contains(&size) {
+                            invalid = true;
+                            err = "Invalid size";
+                            continue;
+                        }
+
+                        color_buf.clear();
+                        for j in (1..=args.len().saturating_sub(size)).step_by(size) {
+                            let color = args[j..j + size].to_vec();
+                            color_buf.push(Color::new(color).convert(colors.vertex, colors.working));
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "texcoord" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.is_empty() {
+                            invalid = true;
+                            err = "Expected a size and values";
+                            continue;
+                        }
+
+                        let size = args[0] as usize;
+                        if size != 2 {
+                            invalid = true;
+                            err = "Invalid size";
+                            continue;
+                        }
+
+                        texcoord_buf.clear();
+                        for j in (1..=args.len().saturating_sub(2)).step_by(2) {
+                            let texcoord: [f64; 2] = [args[j], args[j + 1]];
+                            texcoord_buf.push(texcoord);
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "normal" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.is_empty() {
+                            invalid = true;
+                            err = "Expected a size and values";
+                            continue;
+                        }
+
+                        let size = args[0] as usize;
+                        if size != 3 {
+                            invalid = true;
+                            err = "Invalid size";
+                            continue;
+                        }
+
+                        normal_buf.clear();
+                        for j in (1..=args.len().saturating_sub(3)).step_by(3) {
+                            let normal: [f64; 3] = [args[j], args[j + 1], args[j + 2]];
+                            normal_buf.push(normal);
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "tangent" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.is_empty() {
+                            invalid = true;
+                            err = "Expected a size and values";
+                            continue;
+                        }
+
+                        let size = args[0] as usize;
+                        if !(3..=4).contains(&size) {
+                            invalid = true;
+                            err = "Invalid size";
+                            continue;
+                        }
+
+                        tangent_buf.clear();
+                        for j in (1..=args.len().saturating_sub(size)).step_by(size) {
+                            // The fourth component is the handedness of the bitangent
+                            let w = if size > 3 { args[j + 3] } else { 1f64 };
+                            let tangent: [f64; 4] = [args[j], args[j + 1], args[j + 2], w];
+                            tangent_buf.push(tangent);
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "pointsize" => {
+                    if let Ok(args) = read_args::<f64>(fields[1..].iter()) {
+                        if args.is_empty() {
+                            invalid = true;
+                            err = "Expected a size and values";
+                            continue;
+                        }
+
+                        let size = args[0] as usize;
+                        if size != 1 {
                             invalid = true;
-                            err = "Invalid values";
-                        }
-                    }
-                    "drawArraysTriangles" => {
-                        if let Ok(args) = read_args::<usize>(fields[1..].iter()) {
-                            let first = args[0];
-                            let count = args[1];
-
-                            for j in (0..=count - 3).step_by(3) {
-                                let mut points: Points<10> = Points::<10>::from(
-                                    position_buf.clone(),
-                                    color_buf.clone(),
-                                    texcoord_buf.clone(),
-                                    first + j..first + j + 3,
-                                );
-
-                                draw_triangle(
-                                    &mut img,
-                                    &mut points,
-                                    &uniform_matrix,
-                                    &texture,
-                                    depth,
-                                    hyp,
-                                    cull,
-                                    decals,
-                                );
+                            err = "Invalid size";
+                            continue;
+                        }
+
+                        pointsize_buf = args[1..].to_vec();
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "elements" => {
+                    if let Ok(elements) = read_args::<usize>(fields[1..].iter()) {
+                        element_buf = elements;
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "drawArraysTriangles" => {
+                    if let Ok(args) = read_args::<usize>(fields[1..].iter()) {
+                        if args.len() < 2 {
+                            invalid = true;
+                            err = "Expected a first vertex and a count";
+                            continue;
+                        }
+
+                        let first = args[0];
+                        let count = args[1];
+                        match first.checked_add(count) {
+                            Some(end) if end <= position_buf.len() => {}
+                            _ => {
+                                invalid = true;
+                                err = "Vertex out of range";
+                                continue;
                             }
+                        }
+
+                        for j in (0..count.saturating_sub(2)).step_by(3) {
+                            let mut points: Points<21> = Points::<21>::from(
+                                position_buf.clone(),
+                                color_buf.clone(),
+                                texcoord_buf.clone(),
+                                normal_buf.clone(),
+                                tangent_buf.clone(),
+                                first + j..first + j + 3,
+                            );
 
-                            if let Err(err) = img.save(out_filename.clone(), s_rgb) {
-                                eprintln!("{}", err);
+                            draw_triangle(
+                                target(&mut img, &mut framebuffers, &bound, &mut shadow_pass),
+                                &mut points,
+                                &state,
+                            );
+                        }
+
+                        if draws_to_output(&framebuffers, &bound, &shadow_pass) {
+                            unsaved = true;
+                            if autosave {
+                                save(&mut img, &out_filename, out_format, colors, options, &mut log);
+                                unsaved = false;
                             }
-                        } else {
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "drawElementsTriangles" => {
+                    if let Ok(args) = read_args::<usize>(fields[1..].iter()) {
+                        if args.len() < 2 {
                             invalid = true;
-                            err = "Invalid values";
+                            err = "Expected a count and an offset";
+                            continue;
+                        }
+
+                        let count = args[0];
+                        let offset = args[1];
+                        let elements = match offset.checked_add(count) {
+                            Some(end) if end <= element_buf.len() => &element_buf[offset..end],
+                            _ => {
+                                invalid = true;
+                                err = "Element out of range";
+                                continue;
+                            }
+                        };
+                        if elements.iter().any(|&j| j >= position_buf.len()) {
+                            invalid = true;
+                            err = "Vertex out of range";
+                            continue;
                         }
+
+                        for j in (0..count.saturating_sub(2)).step_by(3) {
+                            let mut points: Points<21> = Points::<21>::from_elements(
+                                &position_buf,
+                                &color_buf,
+                                &texcoord_buf,
+                                &normal_buf,
+                                &tangent_buf,
+                                &elements[j..j + 3],
+                            );
+
+                            draw_triangle(
+                                target(&mut img, &mut framebuffers, &bound, &mut shadow_pass),
+                                &mut points,
+                                &state,
+                            );
+                        }
+
+                        if draws_to_output(&framebuffers, &bound, &shadow_pass) {
+                            unsaved = true;
+                            if autosave {
+                                save(&mut img, &out_filename, out_format, colors, options, &mut log);
+                                unsaved = false;
+                            }
+                        }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
+                    }
+                }
+                "obj" => {
+                    if fields.len() < 2 {
+                        invalid = true;
+                        err = "Expected a file name";
+                        continue;
                     }
-                    "drawElementsTriangles" => {
-                        if let Ok(args) = read_args::<usize>(fields[1..].iter()) {
-                            let count = args[0];
-                            let offset = args[1];
+                    let mesh = match obj::load(&script.resolve(fields[1], cli.asset_dir.as_deref())) {
+                        Ok(mesh) => mesh,
+                        Err(_err) => {
+                            invalid = true;
+                            err = _err;
+                            continue;
+                        }
+                    };
+
+                    // The mesh replaces the vertex buffers, so later draws can reuse it
+                    position_buf = mesh.positions;
+                    texcoord_buf = mesh.texcoords;
+                    normal_buf = mesh.normals;
+                    tangent_buf.clear();
+                    element_buf = mesh.batches.iter().flat_map(|batch| batch.elements.clone()).collect();
 
-                            for j in (0..=count - 3).step_by(3) {
-                                let mut temp_position_buf = vec![];
-                                let mut temp_color_buf = vec![];
-                                let mut temp_texcoord_buf = vec![];
+                    // Materials set the color and texture for their faces only, so the script's own
+                    // are put back afterwards
+                    let vertex_colors = std::mem::take(&mut color_buf);
+                    let texture = state.texture.take();
+                    let mut textures: HashMap<String, Rgba32FImage> = HashMap::new();
+                    for batch in &mesh.batches {
+                        let material = batch.material.as_ref().and_then(|name| mesh.materials.get(name));
+                        let diffuse = material.map_or([1f64; 3], |material| material.diffuse);
+                        let color = Color::new(diffuse.to_vec()).convert(colors.vertex, colors.working);
+                        color_buf = vec![color; position_buf.len()];
 
-                                for k in 0..3 {
-                                    temp_position_buf.push(position_buf[element_buf[offset + j + k]]);
-                                    if element_buf[offset + j + k] < color_buf.len() {
-                                        temp_color_buf.push(color_buf[element_buf[offset + j + k]]);
+                        // The batch's texture is moved into the state while it draws and back into the
+                        // cache after, rather than copied
+                        let path = material.and_then(|material| material.texture.as_ref());
+                        if let Some(path) = path {
+                            if !textures.contains_key(path) {
+                                match read_image(path) {
+                                    Ok(mut image) => {
+                                        convert_image(&mut image, colors.texture, colors.working);
+                                        textures.insert(path.clone(), image);
                                     }
-                                    if element_buf[offset + j + k] < texcoord_buf.len() {
-                                        temp_texcoord_buf.push(texcoord_buf[element_buf[offset + j + k]]);
+                                    Err(_err) => {
+                                        invalid = true;
+                                        err = _err;
                                     }
                                 }
-
-                                let mut points: Points<10> =
-                                    Points::<10>::from(temp_position_buf, temp_color_buf, temp_texcoord_buf, 0..3);
-
-                                draw_triangle(
-                                    &mut img,
-                                    &mut points,
-                                    &uniform_matrix,
-                                    &texture,
-                                    depth,
-                                    hyp,
-                                    cull,
-                                    decals,
-                                );
                             }
+                            state.texture = textures.remove(path);
+                        }
 
-                            if let Err(err) = img.save(out_filename.clone(), s_rgb) {
-                                eprintln!("{}", err);
-                            }
-                        } else {
-                            invalid = true;
-                            err = "Invalid values";
+                        for triangle in batch.elements.chunks_exact(3) {
+                            let mut points: Points<21> = Points::<21>::from_elements(
+                                &position_buf,
+                                &color_buf,
+                                &texcoord_buf,
+                                &normal_buf,
+                                &tangent_buf,
+                                triangle,
+                            );
+
+                            draw_triangle(
+                                target(&mut img, &mut framebuffers, &bound, &mut shadow_pass),
+                                &mut points,
+                                &state,
+                            );
+                        }
+                        if let (Some(path), Some(image)) = (path, state.texture.take()) {
+                            textures.insert(path.clone(), image);
                         }
+                        log.info(format!(
+                            "Drew {} triangles of {}",
+                            batch.elements.len() / 3,
+                            batch.group
+                        ));
                     }
-                    "drawArraysPoints" => {
-                        if let Ok(args) = read_args::<usize>(fields[1..].iter()) {
-                            let first = args[0];
-                            let count = args[1];
+                    state.texture = texture;
+                    color_buf = vertex_colors;
 
-                            for j in first..first + count {
-                                let mut point: Point<11> = Point::<11>::from((
-                                    position_buf[j],
-                                    if j < color_buf.len() {
-                                        color_buf[j]
-                                    } else {
-                                        Color::new(vec![0f64; 4])
-                                    },
-                                    [f64::default(); 2], // No texcoords for points
-                                    pointsize_buf[j],
-                                ));
+                    if draws_to_output(&framebuffers, &bound, &shadow_pass) {
+                        unsaved = true;
+                        if autosave {
+                            save(&mut img, &out_filename, out_format, colors, options, &mut log);
+                            unsaved = false;
+                        }
+                    }
+                }
+                "drawArraysPoints" => {
+                    if let Ok(args) = read_args::<usize>(fields[1..].iter()) {
+                        if args.len() < 2 {
+                            invalid = true;
+                            err = "Expected a first vertex and a count";
+                            continue;
+                        }
 
-                                draw_point(&mut img, &mut point, &uniform_matrix, &texture, depth, decals);
+                        let first = args[0];
+                        let count = args[1];
+                        let end = match first.checked_add(count) {
+                            Some(end) if end <= position_buf.len() => end,
+                            _ => {
+                                invalid = true;
+                                err = "Vertex out of range";
+                                continue;
                             }
+                        };
+                        if end > pointsize_buf.len() {
+                            invalid = true;
+                            err = "Missing point sizes";
+                            continue;
+                        }
+
+                        for j in first..end {
+                            let mut point: Point<22> = Point::<22>::from((
+                                position_buf[j],
+                                if j < color_buf.len() {
+                                    color_buf[j]
+                                } else {
+                                    Color::new(vec![0f64; 4])
+                                },
+                                [f64::default(); 2], // No texcoords for points
+                                pointsize_buf[j],
+                            ));
 
-                            if let Err(err) = img.save(out_filename.clone(), s_rgb) {
-                                eprintln!("{}", err);
+                            draw_point(
+                                target(&mut img, &mut framebuffers, &bound, &mut shadow_pass),
+                                &mut point,
+                                &state,
+                            );
+                        }
+
+                        if draws_to_output(&framebuffers, &bound, &shadow_pass) {
+                            unsaved = true;
+                            if autosave {
+                                save(&mut img, &out_filename, out_format, colors, options, &mut log);
+                                unsaved = false;
                             }
-                        } else {
-                            invalid = true;
-                            err = "Invalid values";
                         }
+                    } else {
+                        invalid = true;
+                        err = "Invalid values";
                     }
-                    _ => {}
                 }
+                _ => log.info(format!("Ignoring unknown command {} on {}", fields[0], location)),
             }
         }
         if invalid {
-            eprintln!("Error on line {}: {}.", line_no, err);
+            log.line_error(&location, err);
+        }
+
+        // Anything drawn since the last save is written once at the end, rather than after every draw
+        if unsaved {
+            save(&mut img, &out_filename, out_format, colors, options, &mut log);
         }
+    } else {
+        log.io_error("Unable to open script");
     }
+
+    log.info(format!("Finished in {:.2?}", start.elapsed()));
+    return log.exit_code();
 }
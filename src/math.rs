@@ -0,0 +1,353 @@
+//! Fixed-size vectors and matrices for the vertex pipeline. Everything lives on the stack in plain
+//! arrays, so transforming a vertex never allocates and the loops are easy for the compiler to
+//! vectorize.
+
+use std::ops::Mul;
+
+pub(crate) type Vec3 = [f64; 3];
+
+pub(crate) fn add(a: Vec3, b: Vec3) -> Vec3 {
+    return [a[0] + b[0], a[1] + b[1], a[2] + b[2]];
+}
+
+pub(crate) fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    return [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+}
+
+pub(crate) fn scale(a: Vec3, s: f64) -> Vec3 {
+    return a.map(|c| c * s);
+}
+
+pub(crate) fn dot(a: Vec3, b: Vec3) -> f64 {
+    return a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+}
+
+pub(crate) fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    return [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+}
+
+/// Scales `a` to unit length, leaving the zero vector as it is.
+pub(crate) fn normalize(a: Vec3) -> Vec3 {
+    let length = dot(a, a).sqrt();
+    if length == 0f64 {
+        return a;
+    }
+    return scale(a, 1f64 / length);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Vec4(pub(crate) [f64; 4]);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Mat4 {
+    // Column-major, the same order the matrix commands take their values in
+    columns: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub(crate) fn identity() -> Self {
+        return Self::from_rows([
+            [1f64, 0f64, 0f64, 0f64],
+            [0f64, 1f64, 0f64, 0f64],
+            [0f64, 0f64, 1f64, 0f64],
+            [0f64, 0f64, 0f64, 1f64],
+        ]);
+    }
+
+    pub(crate) fn from_column_major(values: [f64; 16]) -> Self {
+        return Self {
+            columns: std::array::from_fn(|j| std::array::from_fn(|i| values[4 * j + i])),
+        };
+    }
+
+    /// Builds a matrix from its rows, which reads like the matrix itself when written out in code.
+    pub(crate) fn from_rows(rows: [[f64; 4]; 4]) -> Self {
+        return Self {
+            columns: std::array::from_fn(|j| std::array::from_fn(|i| rows[i][j])),
+        };
+    }
+
+    pub(crate) fn get(&self, row: usize, column: usize) -> f64 {
+        return self.columns[column][row];
+    }
+
+    /// The rows of the upper-left 3x3 block, i.e. the linear part of an affine transform.
+    pub(crate) fn linear_rows(&self) -> [Vec3; 3] {
+        return std::array::from_fn(|i| std::array::from_fn(|j| self.get(i, j)));
+    }
+}
+
+impl Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+
+    fn mul(self, rhs: Vec4) -> Self::Output {
+        // Sum of the columns scaled by the vector's components, which maps directly onto SIMD lanes
+        let mut result = [0f64; 4];
+        for (column, &scale) in self.columns.iter().zip(rhs.0.iter()) {
+            for (r, &c) in result.iter_mut().zip(column.iter()) {
+                *r += c * scale;
+            }
+        }
+        return Vec4(result);
+    }
+}
+
+impl Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Self::Output {
+        return Self {
+            columns: rhs.columns.map(|column| (self * Vec4(column)).0),
+        };
+    }
+}
+
+/// A rotation quaternion `w + xi + yj + zk`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Quat {
+    pub(crate) w: f64,
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) z: f64,
+}
+
+impl Quat {
+    pub(crate) fn identity() -> Self {
+        return Self {
+            w: 1f64,
+            x: 0f64,
+            y: 0f64,
+            z: 0f64,
+        };
+    }
+
+    /// Counter-clockwise rotation by `degrees` about `axis`. A zero axis gives no rotation.
+    pub(crate) fn from_axis_angle(degrees: f64, axis: Vec3) -> Self {
+        let [x, y, z] = normalize(axis);
+        let (s, c) = (degrees.to_radians() / 2f64).sin_cos();
+        return Self {
+            w: c,
+            x: x * s,
+            y: y * s,
+            z: z * s,
+        }
+        .normalize();
+    }
+
+    /// Euler angles in degrees about the axes named by `order`, e.g. `"zyx"`. The rotations compose
+    /// like successive `rotate` commands, so the last axis is applied to the vertices first.
+    pub(crate) fn from_euler(order: &str, degrees: [f64; 3]) -> Result<Self, &'static str> {
+        let order = order.to_ascii_lowercase();
+        let axes = order.as_bytes();
+        if axes.len() != 3 || axes[0] == axes[1] || axes[1] == axes[2] {
+            return Err("Invalid rotation order");
+        }
+
+        let mut result = Self::identity();
+        for (&axis, &angle) in axes.iter().zip(degrees.iter()) {
+            let axis = match axis {
+                b'x' => [1f64, 0f64, 0f64],
+                b'y' => [0f64, 1f64, 0f64],
+                b'z' => [0f64, 0f64, 1f64],
+                _ => return Err("Invalid rotation order"),
+            };
+            result = result * Self::from_axis_angle(angle, axis);
+        }
+        return Ok(result);
+    }
+
+    pub(crate) fn dot(self, rhs: Quat) -> f64 {
+        return self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z;
+    }
+
+    /// Scales to unit length, which every rotation quaternion needs. The zero quaternion becomes
+    /// the identity.
+    pub(crate) fn normalize(self) -> Self {
+        let length = self.dot(self).sqrt();
+        if length == 0f64 {
+            return Self::identity();
+        }
+        return Self {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        };
+    }
+
+    /// Spherical linear interpolation from `self` at `t = 0` to `rhs` at `t = 1`, along the shorter
+    /// arc.
+    pub(crate) fn slerp(self, rhs: Quat, t: f64) -> Self {
+        let (a, mut b) = (self.normalize(), rhs.normalize());
+        let mut cos = a.dot(b);
+
+        // q and -q are the same rotation, so flip to take the short way round
+        if cos < 0f64 {
+            b = Self {
+                w: -b.w,
+                x: -b.x,
+                y: -b.y,
+                z: -b.z,
+            };
+            cos = -cos;
+        }
+
+        // Nearly parallel quaternions make sin(theta) vanish, but lerping is accurate there
+        let (s_a, s_b) = if cos > 0.9995f64 {
+            (1f64 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1f64 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+
+        return Self {
+            w: s_a * a.w + s_b * b.w,
+            x: s_a * a.x + s_b * b.x,
+            y: s_a * a.y + s_b * b.y,
+            z: s_a * a.z + s_b * b.z,
+        }
+        .normalize();
+    }
+
+    pub(crate) fn to_mat4(self) -> Mat4 {
+        let Self { w, x, y, z } = self.normalize();
+        return Mat4::from_rows([
+            [
+                1f64 - 2f64 * (y * y + z * z),
+                2f64 * (x * y - w * z),
+                2f64 * (x * z + w * y),
+                0f64,
+            ],
+            [
+                2f64 * (x * y + w * z),
+                1f64 - 2f64 * (x * x + z * z),
+                2f64 * (y * z - w * x),
+                0f64,
+            ],
+            [
+                2f64 * (x * z - w * y),
+                2f64 * (y * z + w * x),
+                1f64 - 2f64 * (x * x + y * y),
+                0f64,
+            ],
+            [0f64, 0f64, 0f64, 1f64],
+        ]);
+    }
+}
+
+impl Mul<Quat> for Quat {
+    type Output = Quat;
+
+    fn mul(self, rhs: Quat) -> Self::Output {
+        return Self {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Mat4, b: Mat4) {
+        for row in 0..4 {
+            for column in 0..4 {
+                let (x, y) = (a.get(row, column), b.get(row, column));
+                assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    fn apply(rotation: Quat, v: Vec3) -> Vec3 {
+        let Vec4([x, y, z, _]) = rotation.to_mat4() * Vec4([v[0], v[1], v[2], 1f64]);
+        return [x, y, z].map(|c| (c * 1e9).round() / 1e9 + 0f64);
+    }
+
+    /// Counter-clockwise rotation about x, y or z by `degrees`, written out by hand.
+    fn rotation(axis: usize, degrees: f64) -> Mat4 {
+        let (s, c) = degrees.to_radians().sin_cos();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut rows = [[0f64; 4]; 4];
+        rows[axis][axis] = 1f64;
+        rows[3][3] = 1f64;
+        rows[u][u] = c;
+        rows[u][v] = -s;
+        rows[v][u] = s;
+        rows[v][v] = c;
+        return Mat4::from_rows(rows);
+    }
+
+    #[test]
+    fn euler_order_applies_the_last_axis_first() {
+        // y then x takes +x to -z and then to +y
+        let xyz = Quat::from_euler("xyz", [90f64, 90f64, 0f64]).unwrap();
+        assert_eq!(apply(xyz, [1f64, 0f64, 0f64]), [0f64, 1f64, 0f64]);
+        // x then y leaves +x alone and then takes it to -z
+        let yxz = Quat::from_euler("YXZ", [90f64, 90f64, 0f64]).unwrap();
+        assert_eq!(apply(yxz, [1f64, 0f64, 0f64]), [0f64, 0f64, -1f64]);
+
+        assert!(Quat::from_euler("xXy", [0f64; 3]).is_err());
+        assert!(Quat::from_euler("xy", [0f64; 3]).is_err());
+        assert!(Quat::from_euler("xyw", [0f64; 3]).is_err());
+    }
+
+    #[test]
+    fn euler_matrix_matches_successive_rotations() {
+        let [a, b, c] = [30f64, -45f64, 110f64];
+        let expected = rotation(2, a) * rotation(1, b) * rotation(0, c);
+        assert_close(Quat::from_euler("zyx", [a, b, c]).unwrap().to_mat4(), expected);
+    }
+
+    #[test]
+    fn slerp_endpoints_and_midpoint() {
+        let from = Quat::from_axis_angle(10f64, [0f64, 0f64, 1f64]);
+        let to = Quat::from_axis_angle(90f64, [0f64, 0f64, 1f64]);
+
+        assert_close(from.slerp(to, 0f64).to_mat4(), from.to_mat4());
+        assert_close(from.slerp(to, 1f64).to_mat4(), to.to_mat4());
+        assert_close(from.slerp(to, 0.5f64).to_mat4(), rotation(2, 50f64));
+    }
+
+    #[test]
+    fn slerp_takes_the_short_way_round() {
+        let from = Quat::identity();
+        let to = Quat::from_axis_angle(90f64, [1f64, 0f64, 0f64]);
+        let negated = Quat {
+            w: -to.w,
+            x: -to.x,
+            y: -to.y,
+            z: -to.z,
+        };
+
+        // -q is the same rotation as q, so halfway is still 45 degrees rather than 135
+        assert_close(from.slerp(negated, 0.5f64).to_mat4(), rotation(0, 45f64));
+        // Exactly opposite quaternions are the same rotation, which is all there is to interpolate
+        let opposite = Quat {
+            w: -from.w,
+            x: -from.x,
+            y: -from.y,
+            z: -from.z,
+        };
+        assert_close(from.slerp(opposite, 0.5f64).to_mat4(), Mat4::identity());
+    }
+
+    #[test]
+    fn slerp_between_nearly_equal_rotations() {
+        let from = Quat::from_axis_angle(20f64, [0f64, 1f64, 0f64]);
+        let to = Quat::from_axis_angle(20.001f64, [0f64, 1f64, 0f64]);
+        let halfway = from.slerp(to, 0.5f64);
+
+        assert!([halfway.w, halfway.x, halfway.y, halfway.z]
+            .iter()
+            .all(|c| c.is_finite()));
+        assert_close(halfway.to_mat4(), rotation(1, 20.0005f64));
+    }
+}
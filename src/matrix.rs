@@ -0,0 +1,118 @@
+use crate::math::{cross, dot, normalize, Mat4, Quat, Vec3};
+
+/// Builds a 4x4 matrix from 16 column-major values, as given to the matrix commands.
+pub(crate) fn from_column_major(args: &[f64]) -> Result<Mat4, &'static str> {
+    if let Ok(values) = <[f64; 16]>::try_from(args) {
+        return Ok(Mat4::from_column_major(values));
+    }
+    return Err("Expected 16 values");
+}
+
+pub(crate) fn translation(x: f64, y: f64, z: f64) -> Mat4 {
+    return Mat4::from_rows([
+        [1f64, 0f64, 0f64, x],
+        [0f64, 1f64, 0f64, y],
+        [0f64, 0f64, 1f64, z],
+        [0f64, 0f64, 0f64, 1f64],
+    ]);
+}
+
+pub(crate) fn scaling(x: f64, y: f64, z: f64) -> Mat4 {
+    return Mat4::from_rows([
+        [x, 0f64, 0f64, 0f64],
+        [0f64, y, 0f64, 0f64],
+        [0f64, 0f64, z, 0f64],
+        [0f64, 0f64, 0f64, 1f64],
+    ]);
+}
+
+/// Counter-clockwise rotation by `degrees` about the axis (x, y, z), as with `glRotate`.
+pub(crate) fn rotation(degrees: f64, x: f64, y: f64, z: f64) -> Result<Mat4, &'static str> {
+    if [x, y, z] == [0f64; 3] {
+        return Err("Rotation axis must be non-zero");
+    }
+    return Ok(Quat::from_axis_angle(degrees, [x, y, z]).to_mat4());
+}
+
+fn direction(a: Vec3) -> Result<Vec3, &'static str> {
+    let a = normalize(a);
+    if a == [0f64; 3] {
+        return Err("Degenerate camera vectors");
+    }
+    return Ok(a);
+}
+
+// The camera helpers below follow the same convention as the hand-written matrices in the input
+// scripts: eye space looks down +z with y pointing down the image, and the near plane maps to a
+// depth of -1.
+
+/// View matrix for a camera at `eye` looking at `center`, with `up` pointing up the image.
+pub(crate) fn look_at(eye: Vec3, center: Vec3, up: Vec3) -> Result<Mat4, &'static str> {
+    let f = direction([center[0] - eye[0], center[1] - eye[1], center[2] - eye[2]])?;
+    let s = direction(cross(f, up))?;
+    let u = cross(s, f);
+
+    return Ok(Mat4::from_rows([
+        [s[0], s[1], s[2], -dot(s, eye)],
+        [-u[0], -u[1], -u[2], dot(u, eye)],
+        [f[0], f[1], f[2], -dot(f, eye)],
+        [0f64, 0f64, 0f64, 1f64],
+    ]));
+}
+
+/// Off-axis perspective projection onto the near plane, with `top` towards the top of the image.
+pub(crate) fn frustum(left: f64, right: f64, bottom: f64, top: f64, near: f64, far: f64) -> Result<Mat4, &'static str> {
+    if left == right || bottom == top || near == far {
+        return Err("Frustum planes must not coincide");
+    }
+    if near <= 0f64 || far <= 0f64 {
+        return Err("Near and far must be positive");
+    }
+
+    return Ok(Mat4::from_rows([
+        [
+            2f64 * near / (right - left),
+            0f64,
+            (right + left) / (left - right),
+            0f64,
+        ],
+        [
+            0f64,
+            2f64 * near / (top - bottom),
+            (top + bottom) / (top - bottom),
+            0f64,
+        ],
+        [
+            0f64,
+            0f64,
+            (far + near) / (far - near),
+            2f64 * far * near / (near - far),
+        ],
+        [0f64, 0f64, 1f64, 0f64],
+    ]));
+}
+
+/// Symmetric perspective projection with a vertical field of view in degrees.
+pub(crate) fn perspective(fovy: f64, aspect: f64, near: f64, far: f64) -> Result<Mat4, &'static str> {
+    if !(0f64..180f64).contains(&fovy) || fovy == 0f64 || aspect <= 0f64 {
+        return Err("Invalid field of view or aspect ratio");
+    }
+
+    let top = near * (fovy.to_radians() / 2f64).tan();
+    let right = top * aspect;
+    return frustum(-right, right, -top, top, near, far);
+}
+
+/// Orthographic projection of the box between the given planes.
+pub(crate) fn ortho(left: f64, right: f64, bottom: f64, top: f64, near: f64, far: f64) -> Result<Mat4, &'static str> {
+    if left == right || bottom == top || near == far {
+        return Err("Frustum planes must not coincide");
+    }
+
+    return Ok(Mat4::from_rows([
+        [2f64 / (right - left), 0f64, 0f64, (right + left) / (left - right)],
+        [0f64, 2f64 / (top - bottom), 0f64, (top + bottom) / (top - bottom)],
+        [0f64, 0f64, 2f64 / (far - near), (far + near) / (near - far)],
+        [0f64, 0f64, 0f64, 1f64],
+    ]));
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::position::Position;
+
+/// The parts of an MTL material the rasterizer can draw.
+pub(crate) struct Material {
+    /// Kd, used as the vertex color
+    pub(crate) diffuse: [f64; 3],
+    /// map_Kd, already joined onto the directory of the MTL file that names it
+    pub(crate) texture: Option<String>,
+}
+
+/// Consecutive faces sharing a group and material, drawn together.
+pub(crate) struct Batch {
+    pub(crate) group: String,
+    pub(crate) material: Option<String>,
+    /// Three per triangle, indexing the mesh's vertex buffers
+    pub(crate) elements: Vec<usize>,
+}
+
+/// A Wavefront OBJ mesh, with a vertex for each distinct combination of position, texture
+/// coordinate and normal its faces use. The texcoord and normal buffers are empty when no face has
+/// them.
+pub(crate) struct Mesh {
+    pub(crate) positions: Vec<Position>,
+    pub(crate) texcoords: Vec<[f64; 2]>,
+    pub(crate) normals: Vec<[f64; 3]>,
+    pub(crate) batches: Vec<Batch>,
+    pub(crate) materials: HashMap<String, Material>,
+}
+
+/// The first `N` values in `fields`, of which the first `required` must be there and the rest
+/// default.
+fn parse<const N: usize>(fields: &[&str], required: usize, defaults: [f64; N]) -> Result<[f64; N], &'static str> {
+    if fields.len() < required {
+        return Err("Invalid OBJ vertex");
+    }
+    let mut result = defaults;
+    for (value, field) in result.iter_mut().zip(fields) {
+        *value = field.parse::<f64>().map_err(|_| "Invalid OBJ vertex")?;
+    }
+    return Ok(result);
+}
+
+/// Index `field` of an OBJ face refers to: 1-based, or negative to count back from the last one.
+fn index(field: &str, count: usize) -> Result<usize, &'static str> {
+    let index = field.parse::<i64>().map_err(|_| "Invalid OBJ face")?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err("OBJ face refers to a missing vertex");
+    }
+    return Ok(resolved as usize);
+}
+
+/// Loads the OBJ file at `path` and the MTL libraries it uses, which are relative to it.
+pub(crate) fn load(path: &str) -> Result<Mesh, &'static str> {
+    let text = fs::read_to_string(path).map_err(|_| "Unable to open OBJ file")?;
+    let dir = Path::new(path).parent().map_or(PathBuf::new(), Path::to_path_buf);
+
+    let mut raw_positions: Vec<[f64; 4]> = vec![];
+    let mut raw_texcoords: Vec<[f64; 2]> = vec![];
+    let mut raw_normals: Vec<[f64; 3]> = vec![];
+
+    let mut mesh = Mesh {
+        positions: vec![],
+        texcoords: vec![],
+        normals: vec![],
+        batches: vec![],
+        materials: HashMap::new(),
+    };
+    let mut has_texcoords = false;
+    let mut has_normals = false;
+    // Each distinct (position, texcoord, normal) a face uses, and the vertex it became
+    let mut vertices: HashMap<(usize, Option<usize>, Option<usize>), usize> = HashMap::new();
+
+    let mut group = String::from("default");
+    let mut material: Option<String> = None;
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap();
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.is_empty() {
+            continue;
+        }
+
+        match fields[0] {
+            "v" => raw_positions.push(parse(&fields[1..], 3, [0f64, 0f64, 0f64, 1f64])?),
+            "vt" => {
+                let [u, v] = parse(&fields[1..], 1, [0f64; 2])?;
+                // OBJ counts v up from the bottom of the texture, the rasterizer down from the top
+                raw_texcoords.push([u, 1f64 - v]);
+            }
+            "vn" => raw_normals.push(parse(&fields[1..], 3, [0f64; 3])?),
+            "f" => {
+                if fields.len() < 4 {
+                    return Err("OBJ faces need at least 3 vertices");
+                }
+
+                let mut polygon = vec![];
+                for field in &fields[1..] {
+                    let mut parts = field.split('/');
+                    let position = index(parts.next().unwrap(), raw_positions.len())?;
+                    let texcoord = match parts.next() {
+                        Some("") | None => None,
+                        Some(part) => Some(index(part, raw_texcoords.len())?),
+                    };
+                    let normal = match parts.next() {
+                        Some("") | None => None,
+                        Some(part) => Some(index(part, raw_normals.len())?),
+                    };
+                    has_texcoords |= texcoord.is_some();
+                    has_normals |= normal.is_some();
+
+                    let vertex = *vertices.entry((position, texcoord, normal)).or_insert_with(|| {
+                        mesh.positions.push(Position::new(raw_positions[position].to_vec()));
+                        mesh.texcoords.push(texcoord.map_or([0f64; 2], |i| raw_texcoords[i]));
+                        mesh.normals.push(normal.map_or([0f64; 3], |i| raw_normals[i]));
+                        mesh.positions.len() - 1
+                    });
+                    polygon.push((vertex, raw_positions[position]));
+                }
+
+                let same_batch = mesh
+                    .batches
+                    .last()
+                    .is_some_and(|batch| batch.group == group && batch.material == material);
+                if !same_batch {
+                    mesh.batches.push(Batch {
+                        group: group.clone(),
+                        material: material.clone(),
+                        elements: vec![],
+                    });
+                }
+                let elements = &mut mesh.batches.last_mut().unwrap().elements;
+                for triangle in triangulate(&polygon.iter().map(|&(_, p)| p).collect::<Vec<[f64; 4]>>()) {
+                    elements.extend(triangle.map(|i| polygon[i].0));
+                }
+            }
+            "g" | "o" => group = fields[1..].join(" "),
+            "usemtl" => material = fields.get(1).map(|&name| String::from(name)),
+            "mtllib" => {
+                for library in &fields[1..] {
+                    load_materials(&dir.join(library), &mut mesh.materials)?;
+                }
+            }
+            // Smoothing groups, lines, points and anything else don't affect triangles
+            _ => {}
+        }
+    }
+
+    if !has_texcoords {
+        mesh.texcoords.clear();
+    }
+    if !has_normals {
+        mesh.normals.clear();
+    }
+    return Ok(mesh);
+}
+
+fn load_materials(path: &Path, materials: &mut HashMap<String, Material>) -> Result<(), &'static str> {
+    let text = fs::read_to_string(path).map_err(|_| "Unable to open MTL file")?;
+    let dir = path.parent().map_or(PathBuf::new(), Path::to_path_buf);
+
+    let mut current: Option<String> = None;
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap();
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.is_empty() {
+            continue;
+        }
+
+        if fields[0] == "newmtl" {
+            let name = String::from(*fields.get(1).ok_or("Invalid MTL material")?);
+            materials.insert(
+                name.clone(),
+                Material {
+                    diffuse: [1f64; 3],
+                    texture: None,
+                },
+            );
+            current = Some(name);
+            continue;
+        }
+        let Some(material) = current.as_ref().and_then(|name| materials.get_mut(name)) else {
+            continue;
+        };
+        match fields[0] {
+            "Kd" => material.diffuse = parse(&fields[1..], 3, [0f64; 3]).map_err(|_| "Invalid MTL color")?,
+            // Options like -s or -o come before the file name, which is last
+            "map_Kd" if fields.len() > 1 => {
+                material.texture = Some(dir.join(fields[fields.len() - 1]).to_string_lossy().into_owned());
+            }
+            _ => {}
+        }
+    }
+    return Ok(());
+}
+
+/// Splits a polygon into triangles by ear clipping, in the plane it mostly faces, keeping its
+/// winding. Returns indices into `polygon`. What's left of a polygon with no ears, e.g. one that
+/// intersects itself, becomes a fan.
+fn triangulate(polygon: &[[f64; 4]]) -> Vec<[usize; 3]> {
+    if polygon.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method gives the normal of a non-planar or concave polygon too
+    let mut normal = [0f64; 3];
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    // Drop the axis the normal is most along, flipping so the polygon winds counterclockwise
+    let axis = (0..3)
+        .max_by(|&i, &j| normal[i].abs().total_cmp(&normal[j].abs()))
+        .unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let sign = if normal[axis] < 0f64 { -1f64 } else { 1f64 };
+    let points = polygon.iter().map(|p| [p[u], p[v] * sign]).collect::<Vec<[f64; 2]>>();
+
+    let cross = |o: [f64; 2], a: [f64; 2], b: [f64; 2]| (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0]);
+
+    let mut remaining = (0..polygon.len()).collect::<Vec<usize>>();
+    let mut triangles = vec![];
+    let mut i = 0;
+    let mut since_ear = 0;
+    while remaining.len() > 3 && since_ear < remaining.len() {
+        let n = remaining.len();
+        let (prev, cur, next) = (remaining[(i + n - 1) % n], remaining[i % n], remaining[(i + 1) % n]);
+        let (a, b, c) = (points[prev], points[cur], points[next]);
+        let convex = cross(a, b, c) > 0f64;
+        let empty = remaining.iter().all(|&other| {
+            let p = points[other];
+            [prev, cur, next].contains(&other)
+                || cross(a, b, p) < 0f64
+                || cross(b, c, p) < 0f64
+                || cross(c, a, p) < 0f64
+        });
+
+        if convex && empty {
+            triangles.push([prev, cur, next]);
+            remaining.remove(i % n);
+            since_ear = 0;
+        } else {
+            i += 1;
+            since_ear += 1;
+        }
+        i %= remaining.len();
+    }
+    for j in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[j], remaining[j + 1]]);
+    }
+    return triangles;
+}
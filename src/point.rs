@@ -2,37 +2,81 @@ use std::fmt::{Debug, Display, Formatter};
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, Range, Sub};
 
 use image::Rgba;
-use ndarray::{arr2, Array2};
 
-use crate::axis::axis::{A, R, W, X, Y, Z};
+use crate::axis::axis::{A, B, G, LW, LX, NX, NZ, P, R, S, T, TW, TX, W, X, Y, Z};
 use crate::color::Color;
+use crate::math::{Mat4, Vec4};
 use crate::position::Position;
 
-impl Points<10> {
+impl Points<21> {
     pub(crate) fn from(
         position_buf: Vec<Position>,
         color_buf: Vec<Color>,
         texcoord_buf: Vec<[f64; 2]>,
+        normal_buf: Vec<[f64; 3]>,
+        tangent_buf: Vec<[f64; 4]>,
         range: Range<usize>,
-    ) -> Points<10> {
-        let mut result: Vec<Point<10>> = vec![];
+    ) -> Points<21> {
+        let mut result: Vec<Point<21>> = vec![];
         for j in range {
-            let color = if j < color_buf.len() {
-                color_buf[j]
-            } else {
-                Color::new(vec![0f64; 4])
-            };
-            let texcoord = if j < texcoord_buf.len() {
-                texcoord_buf[j]
-            } else {
-                [0f64; 2]
-            };
-            result.push(Point::<10>::from((position_buf[j], color, texcoord)));
+            result.push(vertex(
+                &position_buf,
+                &color_buf,
+                &texcoord_buf,
+                &normal_buf,
+                &tangent_buf,
+                j,
+            ));
         }
-        return Points::<10>(result);
+        return Points::<21>(result);
+    }
+
+    /// The triangle made of the vertices at `elements` in the buffers.
+    pub(crate) fn from_elements(
+        position_buf: &[Position],
+        color_buf: &[Color],
+        texcoord_buf: &[[f64; 2]],
+        normal_buf: &[[f64; 3]],
+        tangent_buf: &[[f64; 4]],
+        elements: &[usize],
+    ) -> Points<21> {
+        return Points::<21>(
+            elements
+                .iter()
+                .map(|&j| vertex(position_buf, color_buf, texcoord_buf, normal_buf, tangent_buf, j))
+                .collect(),
+        );
     }
 }
 
+/// Vertex `j`, with attributes that are missing from the shorter buffers zeroed.
+fn vertex(
+    position_buf: &[Position],
+    color_buf: &[Color],
+    texcoord_buf: &[[f64; 2]],
+    normal_buf: &[[f64; 3]],
+    tangent_buf: &[[f64; 4]],
+    j: usize,
+) -> Point<21> {
+    let color = if j < color_buf.len() {
+        color_buf[j]
+    } else {
+        Color::new(vec![0f64; 4])
+    };
+    let texcoord = if j < texcoord_buf.len() {
+        texcoord_buf[j]
+    } else {
+        [0f64; 2]
+    };
+    let normal = if j < normal_buf.len() { normal_buf[j] } else { [0f64; 3] };
+    let tangent = if j < tangent_buf.len() {
+        tangent_buf[j]
+    } else {
+        [0f64; 4]
+    };
+    return Point::<21>::from((position_buf[j], color, texcoord, normal, tangent));
+}
+
 #[derive(Clone)]
 pub(crate) struct Points<const DIM: usize>(pub(crate) Vec<Point<DIM>>);
 
@@ -55,7 +99,7 @@ impl<const DIM: usize> IntoIterator for Points<DIM> {
 
 impl<const DIM: usize> Points<DIM> {
     pub(crate) fn default() -> Self {
-        Self { 0: vec![] }
+        Self(vec![])
     }
 
     pub(crate) fn append(&mut self, other: &mut Vec<Point<DIM>>) {
@@ -76,13 +120,19 @@ impl<const DIM: usize> Points<DIM> {
             > 0f64;
     }
 
-    pub(crate) fn multiply_by_matrix(&mut self, uniform_matrix: &Array2<f64>) {
+    pub(crate) fn multiply_by_matrix(&mut self, matrix: &Mat4) {
+        for point in &mut self.0 {
+            point.multiply_by_matrix(matrix);
+        }
+    }
+
+    pub(crate) fn project_to_light(&mut self, matrix: &Mat4) {
         for point in &mut self.0 {
-            point.multiply_by_matrix(uniform_matrix);
+            point.project_to_light(matrix);
         }
     }
 
-    pub(crate) fn divide_by_w(&mut self, fields: &Box<[usize]>) {
+    pub(crate) fn divide_by_w(&mut self, fields: &[usize]) {
         for point in &mut self.0 {
             point.divide_by_w(fields);
         }
@@ -94,7 +144,13 @@ impl<const DIM: usize> Points<DIM> {
         }
     }
 
-    pub(crate) fn undivide_by_w(&mut self, fields: &Box<[usize]>) {
+    pub(crate) fn shift_and_scale(&mut self, shift: [f64; 2], scale: f64) {
+        for point in &mut self.0 {
+            point.shift_and_scale(shift, scale);
+        }
+    }
+
+    pub(crate) fn undivide_by_w(&mut self, fields: &[usize]) {
         for point in &mut self.0 {
             point.undivide_by_w(fields);
         }
@@ -106,36 +162,27 @@ pub(crate) struct Point<const DIM: usize> {
     data: [f64; DIM],
 }
 
-impl From<(Position, Color, [f64; 2])> for Point<10> {
-    fn from(value: (Position, Color, [f64; 2])) -> Self {
-        Self {
-            data: <[f64; 10]>::try_from(
-                Point::from(value.0)
-                    .data()
-                    .into_iter()
-                    .chain(Point::from(value.1).data().into_iter())
-                    .chain(value.2)
-                    .collect::<Vec<f64>>(),
-            )
-                .unwrap(),
-        }
+impl From<(Position, Color, [f64; 2], [f64; 3], [f64; 4])> for Point<21> {
+    fn from(value: (Position, Color, [f64; 2], [f64; 3], [f64; 4])) -> Self {
+        let mut data = [0f64; 21];
+        data[X..=W].copy_from_slice(&Point::from(value.0).data());
+        data[R..=A].copy_from_slice(&Point::from(value.1).data());
+        data[S..=T].copy_from_slice(&value.2);
+        data[NX..=NZ].copy_from_slice(&value.3);
+        data[TX..=TW].copy_from_slice(&value.4);
+        Self { data }
     }
 }
 
-impl From<(Position, Color, [f64; 2], f64)> for Point<11> {
+impl From<(Position, Color, [f64; 2], f64)> for Point<22> {
     fn from(value: (Position, Color, [f64; 2], f64)) -> Self {
-        Self {
-            data: <[f64; 11]>::try_from(
-                Point::from(value.0)
-                    .data()
-                    .into_iter()
-                    .chain(Point::from(value.1).data().into_iter())
-                    .chain(value.2)
-                    .chain([value.3])
-                    .collect::<Vec<f64>>(),
-            )
-                .unwrap(),
-        }
+        // No normals, tangents or light-space positions for points
+        let mut data = [0f64; 22];
+        data[X..=W].copy_from_slice(&Point::from(value.0).data());
+        data[R..=A].copy_from_slice(&Point::from(value.1).data());
+        data[S..=T].copy_from_slice(&value.2);
+        data[P] = value.3;
+        Self { data }
     }
 }
 
@@ -154,9 +201,9 @@ impl<const DIM: usize> Display for Point<DIM> {
 
 impl<const DIM: usize> Display for Points<DIM> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Vector:\n")?;
+        writeln!(f, "Vector:")?;
         for p in &self.0 {
-            write!(f, "{}\n", p)?;
+            writeln!(f, "{}", p)?;
         }
         Ok(())
     }
@@ -172,25 +219,35 @@ impl<const DIM: usize> Point<DIM> {
     }
 
     pub(crate) fn pixel(self) -> Rgba<f32> {
-        Rgba(<[f32; 4]>::try_from(self.data[R..=A].iter().map(|&a| a as f32).collect::<Vec<f32>>()).unwrap())
+        Rgba([self[R] as f32, self[G] as f32, self[B] as f32, self[A] as f32])
     }
 
     fn data(self) -> [f64; DIM] {
         return self.data;
     }
 
-    pub(crate) fn multiply_by_matrix(&mut self, uniform_matrix: &Array2<f64>) {
-        let temp: Array2<f64> = arr2(&[[self[X]], [self[Y]], [self[Z]], [self[W]]]);
-        let result = uniform_matrix.dot(&temp);
-        self[X] = result[[0, 0]];
-        self[Y] = result[[1, 0]];
-        self[Z] = result[[2, 0]];
-        self[W] = result[[3, 0]];
+    pub(crate) fn vec3(&self, first: usize) -> [f64; 3] {
+        return [self[first], self[first + 1], self[first + 2]];
+    }
+
+    pub(crate) fn set_vec3(&mut self, first: usize, value: [f64; 3]) {
+        self.data[first..first + 3].copy_from_slice(&value);
+    }
+
+    pub(crate) fn multiply_by_matrix(&mut self, matrix: &Mat4) {
+        let Vec4(result) = *matrix * Vec4([self[X], self[Y], self[Z], self[W]]);
+        self.data[X..=W].copy_from_slice(&result);
     }
 
-    pub(crate) fn divide_by_w(&mut self, fields: &Box<[usize]>) {
+    /// Stores the position transformed by `matrix` in the light-space fields.
+    pub(crate) fn project_to_light(&mut self, matrix: &Mat4) {
+        let Vec4(result) = *matrix * Vec4([self[X], self[Y], self[Z], self[W]]);
+        self.data[LX..=LW].copy_from_slice(&result);
+    }
+
+    pub(crate) fn divide_by_w(&mut self, fields: &[usize]) {
         let w = self[W];
-        for &field in fields.into_iter() {
+        for &field in fields {
             self[field] /= w;
         }
         self[W] = 1f64 / w;
@@ -203,9 +260,19 @@ impl<const DIM: usize> Point<DIM> {
         self[Y] = (y + 1f64) * height as f64 / 2f64;
     }
 
-    fn undivide_by_w(&mut self, fields: &Box<[usize]>) {
+    /// Moves a viewport position by `shift` and then scales it about the origin. Point sizes are
+    /// viewport lengths too, so they scale along.
+    fn shift_and_scale(&mut self, shift: [f64; 2], scale: f64) {
+        self[X] = (self[X] + shift[0]) * scale;
+        self[Y] = (self[Y] + shift[1]) * scale;
+        if DIM > P {
+            self[P] *= scale;
+        }
+    }
+
+    pub(crate) fn undivide_by_w(&mut self, fields: &[usize]) {
         let un_w = self[W];
-        for &field in fields.into_iter() {
+        for &field in fields {
             self[field] /= un_w;
         }
         self[W] = 1f64 / un_w;
@@ -239,14 +306,7 @@ impl<const DIM: usize> Sub for Point<DIM> {
 
     fn sub(self, rhs: Point<DIM>) -> Self::Output {
         return Self::Output {
-            data: <[f64; DIM]>::try_from(
-                (self.data)
-                    .into_iter()
-                    .zip(rhs.data)
-                    .map(|(a, b)| a - b)
-                    .collect::<Vec<f64>>(),
-            )
-                .unwrap(),
+            data: std::array::from_fn(|i| self.data[i] - rhs.data[i]),
         };
     }
 }
@@ -282,14 +342,7 @@ impl<const DIM: usize> Add<Point<DIM>> for Point<DIM> {
 
     fn add(self, rhs: Point<DIM>) -> Self::Output {
         return Self::Output {
-            data: <[f64; DIM]>::try_from(
-                self.data
-                    .into_iter()
-                    .zip(rhs.data)
-                    .map(|(a, b)| a + b)
-                    .collect::<Vec<f64>>(),
-            )
-                .unwrap(),
+            data: std::array::from_fn(|i| self.data[i] + rhs.data[i]),
         };
     }
 }
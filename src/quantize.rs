@@ -0,0 +1,167 @@
+use std::sync::OnceLock;
+
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum Dither {
+    None,
+    Ordered,
+    BlueNoise,
+}
+
+impl Dither {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "none" => Some(Dither::None),
+            "ordered" | "bayer" => Some(Dither::Ordered),
+            "bluenoise" | "blue-noise" => Some(Dither::BlueNoise),
+            _ => None,
+        };
+    }
+
+    /// Threshold in [0, 1) for rounding the value at pixel (x, y). A constant 0.5 is plain rounding.
+    fn threshold(self, x: u32, y: u32) -> f32 {
+        return match self {
+            Dither::None => 0.5f32,
+            Dither::Ordered => (bayer(x % 8, y % 8) as f32 + 0.5f32) / 64f32,
+            Dither::BlueNoise => {
+                let ranks = blue_noise();
+                let coord = ((y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE) as usize;
+                (ranks[coord] as f32 + 0.5f32) / ranks.len() as f32
+            }
+        };
+    }
+}
+
+/// Quantizes `value` in [0, 1] to an integer in [0, max], clamping out-of-range values rather than
+/// letting them wrap. Only color channels are dithered; alpha is always rounded.
+pub(crate) fn quantize(value: f32, max: f32, x: u32, y: u32, channel: usize, dither: Dither) -> f32 {
+    let threshold = if channel < 3 { dither.threshold(x, y) } else { 0.5f32 };
+    return (value.clamp(0f32, 1f32) * max + threshold).floor().min(max);
+}
+
+/// Index of (x, y) in the 8x8 Bayer matrix, built up bit by bit from the 2x2 one.
+fn bayer(x: u32, y: u32) -> u32 {
+    let mut result = 0;
+    for bit in 0..3 {
+        let (x_bit, y_bit) = ((x >> bit) & 1, (y >> bit) & 1);
+        result |= ((x_bit ^ y_bit) << 1 | y_bit) << (2 * (2 - bit));
+    }
+    return result;
+}
+
+const BLUE_NOISE_SIZE: u32 = 64;
+
+/// A tileable blue-noise threshold map, as ranks in [0, BLUE_NOISE_SIZE^2), generated once with
+/// Ulichney's void-and-cluster method.
+fn blue_noise() -> &'static [u32] {
+    static RANKS: OnceLock<Vec<u32>> = OnceLock::new();
+    return RANKS.get_or_init(|| VoidAndCluster::new(BLUE_NOISE_SIZE as usize).ranks());
+}
+
+struct VoidAndCluster {
+    size: usize,
+    // Gaussian weight for each toroidal offset
+    kernel: Vec<f64>,
+    pattern: Vec<bool>,
+    energy: Vec<f64>,
+}
+
+impl VoidAndCluster {
+    fn new(size: usize) -> Self {
+        let sigma = 1.5f64;
+        let mut kernel = vec![0f64; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let dx = x.min(size - x) as f64;
+                let dy = y.min(size - y) as f64;
+                kernel[y * size + x] = (-(dx * dx + dy * dy) / (2f64 * sigma * sigma)).exp();
+            }
+        }
+
+        Self {
+            size,
+            kernel,
+            pattern: vec![false; size * size],
+            energy: vec![0f64; size * size],
+        }
+    }
+
+    fn toggle(&mut self, coord: usize) {
+        let sign = if self.pattern[coord] { -1f64 } else { 1f64 };
+        self.pattern[coord] = !self.pattern[coord];
+
+        let (x0, y0) = (coord % self.size, coord / self.size);
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let dx = (x + self.size - x0) % self.size;
+                let dy = (y + self.size - y0) % self.size;
+                self.energy[y * self.size + x] += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+    }
+
+    /// The set pixel with the most set neighbours.
+    fn tightest_cluster(&self) -> usize {
+        return (0..self.energy.len())
+            .filter(|&coord| self.pattern[coord])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap();
+    }
+
+    /// The unset pixel with the fewest set neighbours.
+    fn largest_void(&self) -> usize {
+        return (0..self.energy.len())
+            .filter(|&coord| !self.pattern[coord])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap();
+    }
+
+    fn ranks(mut self) -> Vec<u32> {
+        let count = self.size * self.size;
+
+        // Start from a fixed pseudo-random tenth of the pixels so the map is the same every run
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let initial = count / 10;
+        while self.pattern.iter().filter(|&&set| set).count() < initial {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let coord = (seed % count as u64) as usize;
+            if !self.pattern[coord] {
+                self.toggle(coord);
+            }
+        }
+
+        // Spread the initial pixels out evenly
+        loop {
+            let cluster = self.tightest_cluster();
+            self.toggle(cluster);
+            let void = self.largest_void();
+            self.toggle(void);
+            if void == cluster {
+                break;
+            }
+        }
+        let initial_pattern = self.pattern.clone();
+        let initial_energy = self.energy.clone();
+
+        let mut ranks = vec![0u32; count];
+
+        // Rank the initial pixels by removing the most clustered first
+        for rank in (0..initial).rev() {
+            let cluster = self.tightest_cluster();
+            self.toggle(cluster);
+            ranks[cluster] = rank as u32;
+        }
+
+        // Then fill the rest of the pattern, always into the largest void
+        self.pattern = initial_pattern;
+        self.energy = initial_energy;
+        for rank in initial..count {
+            let void = self.largest_void();
+            self.toggle(void);
+            ranks[void] = rank as u32;
+        }
+
+        return ranks;
+    }
+}
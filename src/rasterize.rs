@@ -16,7 +16,7 @@ fn dda_setup<const DIM: usize>(a: &mut Point<DIM>, b: &mut Point<DIM>, d: usize)
         a_d = b_d;
     }
 
-    let delta = b.clone() - a.clone();
+    let delta = *b - *a;
 
     let delta_d = delta[d];
     let s = delta / delta_d;
@@ -24,7 +24,7 @@ fn dda_setup<const DIM: usize>(a: &mut Point<DIM>, b: &mut Point<DIM>, d: usize)
     let e = a_d.ceil() - a_d;
     let o = e * s;
 
-    let p = a.clone() + o;
+    let p = *a + o;
 
     return (p, s);
 }
@@ -44,7 +44,7 @@ fn dda_full<const DIM: usize>(mut a: Point<DIM>, mut b: Point<DIM>, d: usize) ->
 }
 
 pub(crate) fn triangle<const DIM: usize>(p: Point<DIM>, q: Point<DIM>, r: Point<DIM>) -> Points<DIM> {
-    let mut sorter = [p, q, r].clone();
+    let mut sorter = [p, q, r];
     sorter.sort_by(|a, b| a[Y].partial_cmp(&b[Y]).unwrap());
     let [t, m, b] = sorter;
 
@@ -69,8 +69,12 @@ pub(crate) fn triangle<const DIM: usize>(p: Point<DIM>, q: Point<DIM>, r: Point<
 }
 
 pub(crate) fn square<const DIM: usize>(center: Point<DIM>) -> Points<DIM> {
-    let mut result: Points<DIM> = Points::<DIM>::default();
+    return quad(square_corners(center));
+}
 
+/// The top left, top right, bottom left and bottom right corners of the point sprite centered on
+/// `center`, with texture coordinates spanning the whole texture.
+pub(crate) fn square_corners<const DIM: usize>(center: Point<DIM>) -> [Point<DIM>; 4] {
     let radius = center[P] / 2f64;
 
     let mut top_left = center;
@@ -98,6 +102,13 @@ pub(crate) fn square<const DIM: usize>(center: Point<DIM>) -> Points<DIM> {
     bottom_right[S] = 1f64;
     bottom_right[T] = 1f64;
 
+    return [top_left, top_right, bottom_left, bottom_right];
+}
+
+/// Rasterizes the quad with corners in the order `square_corners` gives them.
+pub(crate) fn quad<const DIM: usize>(corners: [Point<DIM>; 4]) -> Points<DIM> {
+    let [top_left, top_right, bottom_left, bottom_right] = corners;
+    let mut result: Points<DIM> = Points::<DIM>::default();
     result.append(&mut triangle(top_left, top_right, bottom_left).0);
     result.append(&mut triangle(top_right, bottom_right, bottom_left).0);
 
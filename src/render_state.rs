@@ -0,0 +1,70 @@
+use image::Rgba32FImage;
+
+use crate::lighting::Light;
+use crate::math::Mat4;
+use crate::shadow::ShadowMap;
+
+pub(crate) struct RenderState {
+    pub(crate) uniform_matrix: Mat4,
+    pub(crate) model_matrix: Mat4,
+    pub(crate) view_matrix: Mat4,
+    pub(crate) projection_matrix: Mat4,
+    matrix_stack: Vec<Mat4>,
+    pub(crate) texture: Option<Rgba32FImage>,
+    pub(crate) normal_map: Option<Rgba32FImage>,
+    pub(crate) light: Option<Light>,
+    pub(crate) shadow_map: Option<ShadowMap>,
+    pub(crate) pcf_radius: u32,
+    pub(crate) shadow_bias: f64,
+    pub(crate) depth: bool,
+    pub(crate) depth_only: bool,
+    pub(crate) hyp: bool,
+    pub(crate) cull: bool,
+    pub(crate) decals: bool,
+}
+
+impl RenderState {
+    pub(crate) fn default() -> Self {
+        Self {
+            uniform_matrix: Mat4::identity(),
+            model_matrix: Mat4::identity(),
+            view_matrix: Mat4::identity(),
+            projection_matrix: Mat4::identity(),
+            matrix_stack: vec![],
+            texture: None,
+            normal_map: None,
+            light: None,
+            shadow_map: None,
+            pcf_radius: 1,
+            shadow_bias: 0.005,
+            depth: false,
+            depth_only: false,
+            hyp: false,
+            cull: false,
+            decals: false,
+        }
+    }
+
+    /// The full vertex transform. `uniformMatrix` is treated as an extra, already composed matrix
+    /// applied last, so scripts that only set it behave as before.
+    pub(crate) fn transform(&self) -> Mat4 {
+        return self.uniform_matrix * self.projection_matrix * self.view_matrix * self.model_matrix;
+    }
+
+    /// Right-multiplies the model matrix, so the newest transform applies to vertices first.
+    pub(crate) fn mult_matrix(&mut self, matrix: &Mat4) {
+        self.model_matrix = self.model_matrix * *matrix;
+    }
+
+    pub(crate) fn push_matrix(&mut self) {
+        self.matrix_stack.push(self.model_matrix);
+    }
+
+    pub(crate) fn pop_matrix(&mut self) -> Result<(), &'static str> {
+        if let Some(matrix) = self.matrix_stack.pop() {
+            self.model_matrix = matrix;
+            return Ok(());
+        }
+        return Err("Matrix stack is empty");
+    }
+}
@@ -0,0 +1,154 @@
+use std::f64::consts::PI;
+
+/// Where the FSAA samples sit within a pixel. Each pixel is split into an `fsaa × fsaa` grid of
+/// cells with one sample in each, so every pattern here is stratified.
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum SamplePattern {
+    /// The top-left corner of each cell, as the rasterizer samples whole pixels
+    Grid,
+    /// The center of each cell
+    Centered,
+    /// A grid rotated by atan(1 / fsaa), so no two samples share a row or column and near-horizontal
+    /// and near-vertical edges get `fsaa²` coverage levels rather than `fsaa`
+    Rotated,
+    /// A fixed low-discrepancy jitter within each cell
+    Jittered,
+}
+
+impl SamplePattern {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "grid" | "ordered" => Some(SamplePattern::Grid),
+            "centered" => Some(SamplePattern::Centered),
+            "rotated" | "rgss" => Some(SamplePattern::Rotated),
+            "jittered" => Some(SamplePattern::Jittered),
+            _ => None,
+        };
+    }
+
+    /// Offset of the sample in cell (i, j) from the cell's top-left corner, in [0, 1) cell units.
+    pub(crate) fn offset(self, fsaa: u32, i: u32, j: u32) -> [f64; 2] {
+        let n = fsaa as f64;
+        return match self {
+            SamplePattern::Grid => [0f64, 0f64],
+            SamplePattern::Centered => [0.5f64, 0.5f64],
+            SamplePattern::Rotated => [(j as f64 + 0.5f64) / n, ((fsaa - 1 - i) as f64 + 0.5f64) / n],
+            SamplePattern::Jittered => {
+                let index = j * fsaa + i + 1;
+                [radical_inverse(index, 2), radical_inverse(index, 3)]
+            }
+        };
+    }
+
+    /// Position of the sample in cell (i, j) relative to the pixel's top-left corner, in pixels.
+    pub(crate) fn position(self, fsaa: u32, i: u32, j: u32) -> [f64; 2] {
+        let [dx, dy] = self.offset(fsaa, i, j);
+        return [(i as f64 + dx) / fsaa as f64, (j as f64 + dy) / fsaa as f64];
+    }
+
+    /// The mean sample position, which the resolve filters are centered on.
+    pub(crate) fn center(self, fsaa: u32) -> [f64; 2] {
+        let mut sum = [0f64; 2];
+        for j in 0..fsaa {
+            for i in 0..fsaa {
+                let [x, y] = self.position(fsaa, i, j);
+                sum = [sum[0] + x, sum[1] + y];
+            }
+        }
+        return sum.map(|c| c / fsaa.pow(2) as f64);
+    }
+}
+
+/// Van der Corput radical inverse of `index` in `base`, in [0, 1).
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let mut result = 0f64;
+    let mut scale = 1f64 / base as f64;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale /= base as f64;
+    }
+    return result;
+}
+
+/// How the samples around a pixel are weighted into its final color.
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum ResolveFilter {
+    /// An equal average of the pixel's own samples
+    Box,
+    Tent,
+    Gaussian,
+    Lanczos,
+}
+
+impl ResolveFilter {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "box" => Some(ResolveFilter::Box),
+            "tent" | "triangle" => Some(ResolveFilter::Tent),
+            "gaussian" => Some(ResolveFilter::Gaussian),
+            "lanczos" => Some(ResolveFilter::Lanczos),
+            _ => None,
+        };
+    }
+
+    /// How far from the pixel center, in pixels, samples still get a nonzero weight.
+    pub(crate) fn radius(self) -> f64 {
+        return match self {
+            ResolveFilter::Box => 0.5f64,
+            ResolveFilter::Tent => 1f64,
+            ResolveFilter::Gaussian => 1.5f64,
+            ResolveFilter::Lanczos => 2f64,
+        };
+    }
+
+    /// Weight of a sample at (dx, dy) pixels from the pixel center. Lanczos weights can be negative.
+    pub(crate) fn weight(self, dx: f64, dy: f64) -> f64 {
+        return match self {
+            ResolveFilter::Box => {
+                if dx.abs() <= 0.5f64 && dy.abs() <= 0.5f64 {
+                    1f64
+                } else {
+                    0f64
+                }
+            }
+            ResolveFilter::Tent => (1f64 - dx.abs()).max(0f64) * (1f64 - dy.abs()).max(0f64),
+            // Standard deviation of half a pixel
+            ResolveFilter::Gaussian => (-2f64 * (dx * dx + dy * dy)).exp(),
+            ResolveFilter::Lanczos => lanczos(dx, 2f64) * lanczos(dy, 2f64),
+        };
+    }
+}
+
+fn lanczos(x: f64, a: f64) -> f64 {
+    if x == 0f64 {
+        return 1f64;
+    }
+    if x.abs() >= a {
+        return 0f64;
+    }
+    let pi_x = PI * x;
+    return a * pi_x.sin() * (pi_x / a).sin() / (pi_x * pi_x);
+}
+
+/// How an image is antialiased: its samples per pixel along each axis, where they sit and how they
+/// are resolved. With `msaa` every sample still gets its own coverage and depth test, but each
+/// primitive is only shaded once per pixel.
+#[derive(Copy, Clone)]
+pub(crate) struct Sampling {
+    pub(crate) fsaa: u32,
+    pub(crate) msaa: bool,
+    pub(crate) pattern: SamplePattern,
+    pub(crate) filter: ResolveFilter,
+}
+
+impl Sampling {
+    pub(crate) fn default() -> Self {
+        Self {
+            fsaa: 1,
+            msaa: false,
+            pattern: SamplePattern::Grid,
+            filter: ResolveFilter::Box,
+        }
+    }
+}
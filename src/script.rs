@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{fs, io};
+
+/// How deep includes can nest, which also stops a file including itself forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Where a line of a script came from, for error messages.
+#[derive(Clone, Default)]
+pub(crate) struct Location {
+    /// None for the script being run, otherwise the included file
+    file: Option<Rc<str>>,
+    line: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match &self.file {
+            Some(file) => write!(f, "line {} of {}", self.line, file),
+            None => write!(f, "line {}", self.line),
+        };
+    }
+}
+
+struct Source {
+    file: Option<Rc<str>>,
+    /// What paths in the file are relative to
+    dir: PathBuf,
+    lines: Vec<String>,
+}
+
+struct Loop {
+    /// Index of the first line of the body
+    start: usize,
+    remaining: u64,
+    /// The repeat command's line, for when the loop is never closed
+    line: usize,
+}
+
+/// A file being run: the index of its next line and the loops it's inside.
+struct Frame {
+    source: Rc<Source>,
+    next: usize,
+    loops: Vec<Loop>,
+}
+
+/// The lines of a script in the order they run. `include`, `repeat` and `end` are commands like any
+/// other, so their arguments can use variables; running them moves this through the files and
+/// loops.
+pub(crate) struct Script {
+    frames: Vec<Frame>,
+}
+
+impl Script {
+    /// Opens the script at `path`, or reads one from stdin for `-`.
+    pub(crate) fn open(path: &str) -> io::Result<Self> {
+        let (text, dir) = if path == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            (text, PathBuf::new())
+        } else {
+            let dir = Path::new(path).parent().map_or(PathBuf::new(), Path::to_path_buf);
+            (fs::read_to_string(path)?, dir)
+        };
+        let source = Source {
+            file: None,
+            dir,
+            lines: text.lines().map(String::from).collect(),
+        };
+        return Ok(Self {
+            frames: vec![Frame {
+                source: Rc::new(source),
+                next: 0,
+                loops: vec![],
+            }],
+        });
+    }
+
+    /// The next line to run and where it came from, or None once the script is done. A file that
+    /// ends inside a loop gives an error in place of a line.
+    pub(crate) fn next_line(&mut self) -> Option<(Location, Result<String, &'static str>)> {
+        loop {
+            let frame = self.frames.last_mut()?;
+            if let Some(line) = frame.source.lines.get(frame.next) {
+                frame.next += 1;
+                let location = Location {
+                    file: frame.source.file.clone(),
+                    line: frame.next,
+                };
+                return Some((location, Ok(line.clone())));
+            }
+
+            let frame = self.frames.pop().unwrap();
+            if let Some(unclosed) = frame.loops.first() {
+                let location = Location {
+                    file: frame.source.file.clone(),
+                    line: unclosed.line,
+                };
+                return Some((location, Err("Missing end for repeat")));
+            }
+        }
+    }
+
+    /// The directory paths in the current file are relative to.
+    pub(crate) fn dir(&self) -> &Path {
+        return &self.frames.last().unwrap().source.dir;
+    }
+
+    /// A path from the current file, relative to `base` if there is one and otherwise to the file.
+    /// `-`, standard output, stays as it is.
+    pub(crate) fn resolve(&self, path: &str, base: Option<&str>) -> String {
+        if path == "-" {
+            return String::from(path);
+        }
+        let base = base.map_or(self.dir(), Path::new);
+        return base.join(path).to_string_lossy().into_owned();
+    }
+
+    /// Runs `path`, relative to the current file, before the rest of it.
+    pub(crate) fn include(&mut self, path: &str) -> Result<(), &'static str> {
+        if self.frames.len() > MAX_INCLUDE_DEPTH {
+            return Err("Includes are nested too deeply");
+        }
+        let path = self.dir().join(path);
+        let text = fs::read_to_string(&path).map_err(|_| "Unable to open included file")?;
+        let source = Source {
+            file: Some(Rc::from(path.to_string_lossy().as_ref())),
+            dir: path.parent().map_or(PathBuf::new(), Path::to_path_buf),
+            lines: text.lines().map(String::from).collect(),
+        };
+        self.frames.push(Frame {
+            source: Rc::new(source),
+            next: 0,
+            loops: vec![],
+        });
+        return Ok(());
+    }
+
+    /// Runs the lines up to the matching `end` `count` times, just after reading the repeat command.
+    pub(crate) fn repeat(&mut self, count: u64) -> Result<(), &'static str> {
+        let frame = self.frames.last_mut().unwrap();
+        if count > 0 {
+            frame.loops.push(Loop {
+                start: frame.next,
+                remaining: count,
+                line: frame.next,
+            });
+            return Ok(());
+        }
+
+        // Skip the body, including any loops nested in it
+        let mut depth = 1;
+        while let Some(line) = frame.source.lines.get(frame.next) {
+            frame.next += 1;
+            match line.split_whitespace().next() {
+                Some("repeat") => depth += 1,
+                Some("end") => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+        return Err("Missing end for repeat");
+    }
+
+    /// Goes back to the start of the innermost loop, or past it if that was the last time round.
+    pub(crate) fn end(&mut self) -> Result<(), &'static str> {
+        let frame = self.frames.last_mut().unwrap();
+        let innermost = frame.loops.last_mut().ok_or("end without repeat")?;
+        innermost.remaining -= 1;
+        if innermost.remaining > 0 {
+            frame.next = innermost.start;
+        } else {
+            frame.loops.pop();
+        }
+        return Ok(());
+    }
+}
+
+/// Replaces each `$name` in `line` with the variable's value, where names are letters, digits and
+/// underscores. A `$` not followed by a name is left as it is.
+pub(crate) fn expand(line: &str, variables: &HashMap<String, String>) -> Result<String, &'static str> {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let length = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        if length == 0 {
+            result.push('$');
+        } else {
+            result.push_str(variables.get(&after[..length]).ok_or("Undefined variable")?);
+        }
+        rest = &after[length..];
+    }
+    result.push_str(rest);
+    return Ok(result);
+}
+
+/// Whether `name` can be used as `$name`.
+pub(crate) fn is_variable_name(name: &str) -> bool {
+    return !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
@@ -0,0 +1,75 @@
+use image::Rgba;
+
+use crate::axis::axis::{LW, LX, LY, LZ};
+use crate::depth_image::DepthImage;
+use crate::math::Mat4;
+use crate::point::Point;
+use crate::render_state::RenderState;
+use crate::sampling::Sampling;
+
+/// A light-space depth pre-pass in progress. While it lasts, draws only write depth, into this
+/// buffer instead of the bound framebuffer, and the view and projection matrices belong to the
+/// light.
+pub(crate) struct ShadowPass {
+    pub(crate) depth: DepthImage,
+    camera: [Mat4; 3],
+}
+
+impl ShadowPass {
+    pub(crate) fn begin(width: u32, height: u32, state: &mut RenderState) -> Result<Self, &'static str> {
+        let depth = DepthImage::from_pixel(width, height, Rgba([0f32; 4]), Sampling::default())?;
+        state.depth_only = true;
+        Ok(Self {
+            depth,
+            camera: [state.uniform_matrix, state.projection_matrix, state.view_matrix],
+        })
+    }
+
+    /// Finishes the pass, capturing the light's matrices and restoring the camera's.
+    pub(crate) fn end(self, state: &mut RenderState) -> ShadowMap {
+        let matrix = state.uniform_matrix * state.projection_matrix * state.view_matrix;
+        [state.uniform_matrix, state.projection_matrix, state.view_matrix] = self.camera;
+        state.depth_only = false;
+        ShadowMap {
+            depth: self.depth,
+            matrix,
+        }
+    }
+}
+
+pub(crate) struct ShadowMap {
+    depth: DepthImage,
+    /// World space to the light's clip space
+    pub(crate) matrix: Mat4,
+}
+
+impl ShadowMap {
+    /// The fraction of the `(2 * radius + 1)²` shadow map texels around the fragment's light-space
+    /// position that don't occlude it. Depths are compared in clip space, as the rasterizer stores
+    /// them, with `bias` subtracted from the fragment's to avoid self-shadowing acne.
+    pub(crate) fn visibility<const DIM: usize>(&self, point: &Point<DIM>, radius: u32, bias: f64) -> f32 {
+        let w = point[LW];
+        if w <= 0f64 {
+            // Behind the light, or a point with no light-space position at all
+            return 1f32;
+        }
+
+        let x = ((point[LX] / w + 1f64) * self.depth.width() as f64 / 2f64).floor() as i64;
+        let y = ((point[LY] / w + 1f64) * self.depth.height() as f64 / 2f64).floor() as i64;
+        let z = point[LZ] - bias;
+        let radius = radius as i64;
+
+        let mut lit = 0u32;
+        let mut total = 0u32;
+        for j in y - radius..=y + radius {
+            for i in x - radius..=x + radius {
+                total += 1;
+                let outside = i < 0 || j < 0 || i >= self.depth.width() as i64 || j >= self.depth.height() as i64;
+                if outside || z <= self.depth.depth(i as u32, j as u32) {
+                    lit += 1;
+                }
+            }
+        }
+        return lit as f32 / total as f32;
+    }
+}
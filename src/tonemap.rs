@@ -0,0 +1,57 @@
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum ToneMapOperator {
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "none" | "linear" => Some(ToneMapOperator::None),
+            "reinhard" => Some(ToneMapOperator::Reinhard),
+            "aces" | "filmic" => Some(ToneMapOperator::Aces),
+            _ => None,
+        };
+    }
+
+    fn apply(self, value: f32) -> f32 {
+        return match self {
+            ToneMapOperator::None => value,
+            ToneMapOperator::Reinhard => value / (1f32 + value),
+            // Krzysztof Narkowicz's fit of the ACES reference rendering and output transforms
+            ToneMapOperator::Aces => {
+                let (a, b, c, d, e) = (2.51f32, 0.03f32, 2.43f32, 0.59f32, 0.14f32);
+                (value * (a * value + b) / (value * (c * value + d) + e)).clamp(0f32, 1f32)
+            }
+        };
+    }
+}
+
+/// Maps scene-referred linear colors, which can go well past 1, into the displayable range. Applied
+/// per channel to the resolved image before it's converted to the output color space.
+#[derive(Copy, Clone)]
+pub(crate) struct ToneMap {
+    pub(crate) operator: ToneMapOperator,
+    /// In stops, so each step doubles or halves the scene's brightness
+    pub(crate) exposure: f32,
+}
+
+impl ToneMap {
+    pub(crate) fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::None,
+            exposure: 0f32,
+        }
+    }
+
+    pub(crate) fn is_identity(&self) -> bool {
+        return self.operator == ToneMapOperator::None && self.exposure == 0f32;
+    }
+
+    pub(crate) fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = self.exposure.exp2();
+        // Negative values, e.g. from out-of-gamut colors, aren't light to compress
+        return rgb.map(|value| self.operator.apply((value * scale).max(0f32)));
+    }
+}
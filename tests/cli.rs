@@ -0,0 +1,278 @@
+//! Runs the rasterizer's command line with scripts piped through stdin.
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use common::scratch;
+
+fn run(dir: &Path, args: &[&str], script: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rasterizer"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Commands that fail before reading the script close the pipe early
+    let _ = child.stdin.take().unwrap().write_all(script.as_bytes());
+    return child.wait_with_output().unwrap();
+}
+
+const TRIANGLE: &str = "png 20 30 out.png
+position 2 0 0 19 0 0 29
+color 3 1 0 0 0 1 0 0 0 1
+drawArraysTriangles 0 3
+";
+
+#[test]
+fn flags_override_the_script() {
+    let dir = scratch("flags");
+    let output = run(
+        &dir,
+        &["render", "--width", "8", "--height=6", "-o", "other.png", "-"],
+        TRIANGLE,
+    );
+
+    assert!(output.status.success());
+    assert_eq!(image::open(dir.join("other.png")).unwrap().width(), 8);
+    assert_eq!(image::open(dir.join("other.png")).unwrap().height(), 6);
+    assert!(!dir.join("out.png").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn each_output_is_written() {
+    let dir = scratch("outputs");
+    let script = format!(
+        "{}{}",
+        TRIANGLE.replace("out.png", "first.png"),
+        TRIANGLE.replace("out.png", "second.png")
+    );
+    let output = run(&dir, &["render", "-"], &script);
+
+    assert!(output.status.success());
+    assert!(dir.join("first.png").exists());
+    assert!(dir.join("second.png").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn offscreen_draws_leave_the_output_unwritten() {
+    let dir = scratch("offscreen");
+    let offscreen = TRIANGLE.replace(
+        "drawArraysTriangles",
+        "framebuffer create target 8 8\nbind target\ndrawArraysTriangles",
+    );
+    let shadow = TRIANGLE.replace(
+        "drawArraysTriangles 0 3",
+        "shadowmap begin 8 8\ndrawArraysTriangles 0 3\nshadowmap end",
+    );
+    for script in [offscreen.clone(), shadow] {
+        let output = run(&dir, &["render", "-"], &script);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(!dir.join("out.png").exists());
+    }
+
+    // Until something is drawn into it too
+    let onscreen = format!("{}bind default\ndrawArraysTriangles 0 3\n", offscreen);
+    let output = run(&dir, &["render", "-"], &onscreen);
+    assert!(output.status.success());
+    assert!(dir.join("out.png").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn defines_are_substituted() {
+    let dir = scratch("defines");
+    // Comments aren't expanded, whole lines or the rest of one
+    let script = TRIANGLE.replace("out.png", "$name.png # costs $5, not $undefined");
+    let output = run(
+        &dir,
+        &["render", "-D", "name=defined", "-"],
+        &format!("# costs $5\n{}", script),
+    );
+
+    assert!(output.status.success());
+    assert!(dir.join("defined.png").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn exit_codes() {
+    let dir = scratch("exit");
+    let code = |args: &[&str], script: &str| run(&dir, args, script).status.code().unwrap();
+
+    assert_eq!(code(&["help"], ""), 0);
+    assert_eq!(code(&[], ""), 2);
+    assert_eq!(code(&["render", "--fsaa", "9", "-"], TRIANGLE), 2);
+    assert_eq!(code(&["render", "--bogus", "-"], TRIANGLE), 2);
+    assert_eq!(code(&["render", "-"], "png 10 10 out.png\nposition 2 $undefined\n"), 1);
+    assert_eq!(code(&["render", "-"], "png 10 10 out.png\nrotateEuler\n"), 1);
+    assert_eq!(code(&["render", "-"], "png 10 10 out.png\nrotateEuler xXy 0 0 0\n"), 1);
+    assert_eq!(code(&["render", "-"], "png 10 10 out.png\nrotateEuler XyZ 0 0 0\n"), 0);
+    // Commands missing their arguments are script errors rather than crashes
+    for command in [
+        "fsaa",
+        "msaa",
+        "texture",
+        "normalmap",
+        "position",
+        "color",
+        "drawArraysTriangles",
+        "drawElementsTriangles 3",
+        "drawArraysPoints",
+    ] {
+        assert_eq!(
+            code(&["render", "-"], &format!("png 10 10 out.png\n{}\n", command)),
+            1,
+            "{}",
+            command
+        );
+    }
+    // So are draws that reach past the end of a buffer
+    for draw in [
+        "drawArraysTriangles 1 3",
+        "drawArraysTriangles 18446744073709551615 3",
+        "elements 0 1 2\ndrawElementsTriangles 3 1",
+        "elements 0 1 3\ndrawElementsTriangles 3 0",
+        "drawArraysPoints 0 4",
+        "drawArraysPoints 0 3",
+    ] {
+        let script = format!("png 10 10 out.png\nposition 2 0 0 9 0 0 9\npointsize 1 1 1\n{}\n", draw);
+        assert_eq!(code(&["render", "-"], &script), 1, "{}", draw);
+    }
+    assert_eq!(code(&["render", "missing.txt"], ""), 3);
+    assert_eq!(
+        code(&["render", "-"], &TRIANGLE.replace("out.png", "missing/out.png")),
+        3
+    );
+
+    let quiet = run(&dir, &["render", "--quiet", "-"], "png 10 10 out.png\nfsaa 9\n");
+    assert_eq!(quiet.status.code(), Some(1));
+    assert!(quiet.stderr.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn includes_variables_and_loops() {
+    let dir = scratch("include");
+    fs::create_dir_all(dir.join("shapes")).unwrap();
+    // Included paths are relative to the file that includes them
+    fs::write(dir.join("shapes/scene.txt"), "include triangle.txt\n").unwrap();
+    fs::write(
+        dir.join("shapes/triangle.txt"),
+        TRIANGLE.replace("out.png", "$name.png"),
+    )
+    .unwrap();
+    let script = "set name first
+include shapes/scene.txt
+repeat 2
+repeat 0
+bogus
+end
+save
+end
+set name second
+include shapes/scene.txt
+";
+    let output = run(&dir, &["render", "--verbose", "-"], script);
+
+    assert!(output.status.success());
+    // As are the images it writes
+    assert!(dir.join("shapes/first.png").exists());
+    assert!(dir.join("shapes/second.png").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Once each time round the loop, with the inner loop skipped
+    assert_eq!(stderr.matches("Wrote shapes/first.png").count(), 2);
+    assert!(!stderr.contains("bogus"));
+
+    let unclosed = run(&dir, &["render", "-"], "repeat 2\nset name x\n");
+    assert!(String::from_utf8_lossy(&unclosed.stderr).contains("Error on line 1: Missing end for repeat."));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn paths_are_relative_to_the_script() {
+    let dir = scratch("paths");
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output_dir = dir.to_string_lossy();
+    // The textures are found next to the script, wherever it's run from
+    let output = run(
+        root,
+        &["render", "--output-dir", &output_dir, "input/rast-textures.txt"],
+        "",
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("textures.png").exists());
+
+    // Unless they're looked for elsewhere
+    let output = run(
+        root,
+        &[
+            "render",
+            "--asset-dir",
+            &output_dir,
+            "--output-dir",
+            &output_dir,
+            "input/rast-textures.txt",
+        ],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(1));
+
+    // Standard output isn't a path
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(
+        dir.join("sub/s.txt"),
+        TRIANGLE.replace("png 20 30 out.png", "output ppm 20 30 -"),
+    )
+    .unwrap();
+    let output = run(&dir, &["render", "sub/s.txt"], "");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.starts_with(b"P6"));
+    assert!(!dir.join("sub/-").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn obj_meshes_are_drawn() {
+    let dir = scratch("obj");
+    fs::write(dir.join("mesh.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+    // An L starting from a corner a fan would cover the notch from
+    let mesh = "mtllib mesh.mtl
+v 0.1 0.1 0
+v 0.9 0.1 0
+v 0.9 0.3 0
+v 0.3 0.3 0
+v 0.3 0.9 0
+v 0.1 0.9 0
+g ell
+usemtl red
+f 3 4 5 6 1 2
+";
+    fs::write(dir.join("mesh.obj"), mesh).unwrap();
+    // Draws after the mesh get the script's colors back rather than its material's
+    let script = "png 100 100 out.png
+color 3 0 0 1 0 0 1 0 0 1
+obj mesh.obj
+position 2 0.5 0.5 0.65 0.5 0.5 0.65
+drawArraysTriangles 0 3
+";
+    let output = run(&dir, &["render", "-"], script);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let image = image::open(dir.join("out.png")).unwrap().into_rgba8();
+    assert_eq!(image.get_pixel(60, 80).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(77, 77).0, [0, 0, 255, 255]);
+    assert_eq!(image.get_pixel(80, 80).0[3], 0);
+    assert_eq!(image.get_pixel(20, 20).0[3], 0);
+
+    let missing = run(&dir, &["render", "-"], "png 10 10 out.png\nobj missing.obj\n");
+    assert_eq!(missing.status.code(), Some(1));
+    fs::remove_dir_all(dir).unwrap();
+}
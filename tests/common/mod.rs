@@ -0,0 +1,29 @@
+//! Fixtures shared by the integration tests. Each test crate includes this with `mod common;` and
+//! uses some of it.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory holding the scripts, textures and reference images.
+pub(crate) fn input_dir() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("input");
+}
+
+pub(crate) fn input(name: &str) -> PathBuf {
+    return input_dir().join(name);
+}
+
+/// Creates a directory under the system temp directory for the test `name`, named after the test
+/// crate and process so concurrent runs don't share it. Tests remove it when they pass.
+pub(crate) fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "rasterizer-{}-{}-{}",
+        env!("CARGO_CRATE_NAME"),
+        name,
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
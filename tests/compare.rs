@@ -0,0 +1,62 @@
+//! Runs `rasterizer compare` on the reference images in `input/`.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn compare(a: &str, b: &str, heatmap: &Path) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_rasterizer"))
+        .arg("compare")
+        .arg(common::input(a))
+        .arg(common::input(b))
+        .arg(heatmap)
+        .output()
+        .unwrap();
+}
+
+/// The value printed after `label`.
+fn metric(output: &Output, label: &str) -> f64 {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().find(|line| line.starts_with(label)).unwrap();
+    return line[label.len()..].split_whitespace().next().unwrap().parse().unwrap();
+}
+
+#[test]
+fn identical_images_match_perfectly() {
+    let dir = common::scratch("same");
+    let heatmap = dir.join("heatmap.png");
+    let output = compare("rast-textures.png", "rast-textures.png", &heatmap);
+
+    assert_eq!(metric(&output, "max abs error"), 0f64);
+    assert_eq!(metric(&output, "PSNR"), f64::INFINITY);
+    assert_eq!(metric(&output, "SSIM"), 1f64);
+    assert_eq!(metric(&output, "FLIP"), 0f64);
+    assert_eq!(image::open(&heatmap).unwrap().width(), 256);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn different_images_show_errors() {
+    let dir = common::scratch("diff");
+    let heatmap = dir.join("heatmap.png");
+    let output = compare("rast-textures.png", "rast-decals.png", &heatmap);
+    fs::remove_dir_all(dir).unwrap();
+
+    assert!(metric(&output, "mean abs error") > 0f64);
+    assert!(metric(&output, "PSNR").is_finite());
+    assert!(metric(&output, "SSIM") < 1f64);
+    assert!(metric(&output, "FLIP") > 0f64);
+}
+
+#[test]
+fn different_sizes_are_rejected() {
+    let dir = common::scratch("size");
+    let heatmap = dir.join("heatmap.png");
+    let output = compare("rast-gray.png", "rast-depth.png", &heatmap);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("different sizes"));
+    assert!(!heatmap.exists());
+    fs::remove_dir_all(dir).unwrap();
+}
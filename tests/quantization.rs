@@ -0,0 +1,123 @@
+//! Checks 8-bit quantization against the reference images in `input/`.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use image::{Rgba32FImage, RgbaImage};
+
+const SCRIPT: &str = "rast-smoothcolor.txt";
+
+/// Runs the smoothcolor script in a scratch directory, with its output line replaced by `output`,
+/// `extra` commands inserted after it and every color multiplied by `brightness`. Returns the
+/// directory it wrote to.
+fn render(name: &str, output: &str, extra: &str, brightness: f64) -> PathBuf {
+    let dir = common::scratch(name);
+
+    let mut script = vec![String::from(output), String::from(extra)];
+    for line in fs::read_to_string(common::input(SCRIPT)).unwrap().lines().skip(1) {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.first() == Some(&"color") {
+            let colors = fields[2..]
+                .iter()
+                .map(|c| (c.parse::<f64>().unwrap() * brightness).to_string());
+            script.push(format!(
+                "color {} {}",
+                fields[1],
+                colors.collect::<Vec<String>>().join(" ")
+            ));
+        } else {
+            script.push(String::from(line));
+        }
+    }
+    fs::write(dir.join(SCRIPT), script.join("\n")).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_rasterizer"))
+        .arg(SCRIPT)
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    return dir;
+}
+
+fn render_png(name: &str, size: &str, extra: &str, brightness: f64) -> RgbaImage {
+    let dir = render(name, &format!("png {} out.png", size), extra, brightness);
+    let image = image::open(dir.join("out.png")).unwrap().into_rgba8();
+    fs::remove_dir_all(dir).unwrap();
+    return image;
+}
+
+fn render_exr(size: &str, brightness: f64) -> Rgba32FImage {
+    let dir = render("exr", &format!("exr {} out.exr", size), "", brightness);
+    let image = image::open(dir.join("out.exr")).unwrap().into_rgba32f();
+    fs::remove_dir_all(dir).unwrap();
+    return image;
+}
+
+/// Mean absolute error of 8x8 block averages against the unquantized image, over fully covered
+/// blocks. Rounding errors line up into bands along a gradient and survive the averaging, while
+/// dithering spreads them out so they cancel.
+fn banding(image: &RgbaImage, truth: &Rgba32FImage) -> f64 {
+    let (mut total, mut blocks) = (0f64, 0);
+    for block_y in 0..truth.height() / 8 {
+        for block_x in 0..truth.width() / 8 {
+            let pixels = (0..64).map(|i| (block_x * 8 + i % 8, block_y * 8 + i / 8));
+            if pixels.clone().any(|(x, y)| truth.get_pixel(x, y)[3] < 1f32) {
+                continue;
+            }
+
+            for channel in 0..3 {
+                let error: f64 = pixels
+                    .clone()
+                    .map(|(x, y)| {
+                        image.get_pixel(x, y)[channel] as f64 / 255f64 - truth.get_pixel(x, y)[channel] as f64
+                    })
+                    .sum();
+                total += (error / 64f64).abs();
+            }
+            blocks += 1;
+        }
+    }
+    assert!(blocks > 0);
+    return total / (blocks * 3) as f64;
+}
+
+#[test]
+fn rounding_is_within_a_level_of_reference() {
+    // The reference images were written by truncating, so rounding lands on the same level or the
+    // one above it, never below
+    let reference = image::open(common::input("rast-smoothcolor.png")).unwrap().into_rgba8();
+    let image = render_png("round", "20 30", "", 1f64);
+
+    assert_eq!(image.dimensions(), reference.dimensions());
+    for (a, b) in image.pixels().zip(reference.pixels()) {
+        for channel in 0..4 {
+            assert!(
+                (0..=1).contains(&(a[channel] as i32 - b[channel] as i32)),
+                "{:?} vs {:?}",
+                a,
+                b
+            );
+        }
+    }
+}
+
+// A dim, enlarged smoothcolor, so the gradient only climbs a level every few pixels and rounding
+// leaves wide bands
+const SIZE: &str = "200 300";
+const BRIGHTNESS: f64 = 0.05;
+
+#[test]
+fn dithering_reduces_banding() {
+    let truth = render_exr(SIZE, BRIGHTNESS);
+    let rounded = banding(&render_png("none", SIZE, "", BRIGHTNESS), &truth);
+    let ordered = banding(&render_png("ordered", SIZE, "dither ordered", BRIGHTNESS), &truth);
+    let blue_noise = banding(&render_png("bluenoise", SIZE, "dither bluenoise", BRIGHTNESS), &truth);
+
+    assert!(ordered < rounded);
+    assert!(blue_noise < rounded);
+}